@@ -1,42 +1,122 @@
 #![allow(unused)]
 
+use std::path::Path;
+
 use clap::{value_t, App, Arg, ArgMatches};
+use log::warn;
+use crate::draw::{refresh_policy, RefreshPolicy};
+use crate::hardware::{AutoRotate, PageTurnConfig};
+use crate::keyboard::{chord, KeyboardLayout};
 use crate::processing::PostProcConfig;
-use crate::vnc::Connection;
+use crate::touch::{GestureConfig, StylusAction, StylusConfig};
+use crate::vnc::{ColorFormat, Connection, DEFAULT_LISTEN_PORT};
+
+const DEFAULT_BUTTONS_INPUT: &str = "/dev/input/event0";
 
 pub struct Config<'a> {
     pub connection: Connection<'a>,
+    /// Wait for the server to connect to this port, instead of connecting to `connection.host`.
+    pub listen: Option<u16>,
     pub processing: PostProcConfig,
+    /// Picks the waveform of each update and when the ghosting is cleaned up.
+    pub refresh_policy: Box<dyn RefreshPolicy>,
 
     pub rotate: i8,
+    /// Whether the screen rotates with the device, on models with a G-sensor.
+    pub auto_rotate: AutoRotate,
 
     pub view_only: bool,
     pub touch_input: String,
+    /// Keyboard devices whose keys are sent to the server.
+    pub keyboards: Vec<String>,
+    pub keyboard_layout: KeyboardLayout,
+    /// Thresholds of the finger gestures for right click, scrolling and dragging.
+    pub gestures: GestureConfig,
+    /// What the stylus tip and buttons do.
+    pub stylus: StylusConfig,
+    /// The device with the buttons and the sleep cover sensor of the Kobo itself.
+    pub buttons_input: Option<String>,
+    pub page_turn: PageTurnConfig,
+    /// The device of the G-sensor, on models where it's not the buttons device.
+    pub gyroscope_input: Option<String>,
+    /// Close the session while asleep, instead of keeping it open without updates.
+    pub sleep_disconnect: bool,
+
+    pub reconnect: bool,
 }
 
 impl Config<'static> {
     
     pub fn cli<'a>(matches: &'a ArgMatches) -> Config<'a> {
+        let view_only: bool = matches.value_of("VIEW_ONLY")
+            .unwrap_or("false").trim().parse().unwrap();
         let connection = Connection {
-            host: matches.value_of("HOST").unwrap(),
+            host: matches.value_of("HOST").unwrap_or_default(),
             port: value_t!(matches.value_of("PORT"), u16).unwrap_or(5900),
             username: matches.value_of("USERNAME").clone(),
             password: matches.value_of("PASSWORD").clone(),
             exclusive: matches.is_present("EXCLUSIVE"),
+            tls: matches.is_present("TLS") || matches.is_present("TLS_CA") || matches.is_present("TLS_FINGERPRINT"),
+            tls_ca: matches.value_of("TLS_CA"),
+            tls_fingerprint: matches.value_of("TLS_FINGERPRINT"),
+            format: matches.value_of("FORMAT").unwrap_or("native").parse().unwrap(),
+            local_cursor: !view_only,
+            continuous_updates: matches.value_of("CONTINUOUS_UPDATES")
+            .unwrap_or("false").trim().parse().unwrap(),
+            resize: matches.value_of("RESIZE")
+            .unwrap_or("false").trim().parse().unwrap(),
         };
         let processing = PostProcConfig {
             contrast_exp: value_t!(matches.value_of("CONTRAST"), f32).unwrap_or(1.0),
             contrast_gray_point: value_t!(matches.value_of("GRAYPOINT"), f32).unwrap_or(224.0),
             white_cutoff: value_t!(matches.value_of("WHITECUTOFF"), u8).unwrap_or(255),
         };
+        let default_gestures = GestureConfig::default();
+        let gestures = GestureConfig {
+            tap_slop_mm: value_t!(matches.value_of("TAP_SLOP"), f32).unwrap_or(default_gestures.tap_slop_mm),
+            long_press_ms: value_t!(matches.value_of("LONG_PRESS"), u64).unwrap_or(default_gestures.long_press_ms),
+            double_tap_ms: value_t!(matches.value_of("DOUBLE_TAP"), u64).unwrap_or(default_gestures.double_tap_ms),
+            scroll_step_mm: value_t!(matches.value_of("SCROLL_STEP"), f32).unwrap_or(default_gestures.scroll_step_mm),
+        };
+        let default_stylus = StylusConfig::default();
+        let stylus = StylusConfig {
+            pressure_threshold: value_t!(matches.value_of("STYLUS_PRESSURE"), i32).unwrap_or(default_stylus.pressure_threshold),
+            erase: matches.value_of("STYLUS_ERASE").map(|action| action.parse().unwrap()).unwrap_or(default_stylus.erase),
+            highlight: matches.value_of("STYLUS_HIGHLIGHT").map(|action| action.parse().unwrap()).unwrap_or(default_stylus.highlight),
+        };
+        let default_page_turn = PageTurnConfig::default();
+        let page_turn = PageTurnConfig {
+            backward: matches.value_of("PAGE_BACKWARD").and_then(chord).unwrap_or(default_page_turn.backward),
+            forward: matches.value_of("PAGE_FORWARD").and_then(chord).unwrap_or(default_page_turn.forward),
+            refresh: matches.value_of("PAGE_REFRESH")
+            .unwrap_or("false").trim().parse().unwrap(),
+        };
+        let listen = if matches.is_present("LISTEN") {
+            Some(value_t!(matches.value_of("LISTEN"), u16).unwrap_or(DEFAULT_LISTEN_PORT))
+        } else {
+            None
+        };
         return Config{
             connection,
+            listen,
             processing,
+            refresh_policy: refresh_policy(matches.value_of("REFRESH_POLICY").unwrap_or("balanced")).unwrap(),
             
             rotate: value_t!(matches.value_of("ROTATE"), i8).unwrap_or(1),
-            view_only: matches.value_of("VIEW_ONLY")
-            .unwrap_or("false").trim().parse().unwrap(),
+            auto_rotate: matches.value_of("AUTO_ROTATE").unwrap_or("off").parse().unwrap(),
+            view_only,
             touch_input: matches.value_of("TOUCH_INPUT").unwrap_or("/dev/input/event1").to_string(),
+            keyboards: matches.values_of("KEYBOARD").map(|paths| paths.map(String::from).collect()).unwrap_or_default(),
+            keyboard_layout: matches.value_of("KEYBOARD_LAYOUT").unwrap_or("us").parse().unwrap(),
+            gestures,
+            stylus,
+            buttons_input: matches.value_of("BUTTONS_INPUT").map(String::from).or_else(default_buttons_input),
+            page_turn,
+            gyroscope_input: matches.value_of("GYROSCOPE_INPUT").map(String::from),
+            sleep_disconnect: matches.value_of("SLEEP_DISCONNECT")
+            .unwrap_or("false").trim().parse().unwrap(),
+            reconnect: matches.value_of("RECONNECT")
+            .unwrap_or("true").trim().parse().unwrap(),
         }
     }
 
@@ -46,7 +126,7 @@ impl Config<'static> {
             .arg(
                 Arg::with_name("HOST")
                     .help("server hostname or IP")
-                    .required(true)
+                    .required_unless_present("LISTEN")
                     .index(1),
             )
             .arg(
@@ -56,6 +136,14 @@ impl Config<'static> {
                     .default_value("5900")
                     .takes_value(true)
             )
+            .arg(
+                Arg::with_name("LISTEN")
+                    .help("wait for the server to connect to us on this port (reverse connection), like `vncviewer -listen`")
+                    .long("listen")
+                    .takes_value(true)
+                    .min_values(0)
+                    .default_missing_value("5500"),
+            )
             .arg(
                 Arg::with_name("USERNAME")
                     .help("server username")
@@ -73,6 +161,31 @@ impl Config<'static> {
                     .help("request a non-shared session")
                     .long("exclusive"),
             )
+            .arg(
+                Arg::with_name("TLS")
+                    .help("secure the session with VeNCrypt (TLSNone, TLSPlain, X509None, X509Plain)")
+                    .long("tls"),
+            )
+            .arg(
+                Arg::with_name("TLS_CA")
+                    .help("CA certificate (PEM file) to verify the server's X509 certificate; implies --tls")
+                    .long("tls-ca")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("TLS_FINGERPRINT")
+                    .help("SHA-256 fingerprint of the server's X509 certificate to pin; implies --tls")
+                    .long("tls-fingerprint")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("FORMAT")
+                    .help("pixel format to request: the server's native one, 4 bit gray16 true colour or 8 bit bgr233 true colour")
+                    .long("format")
+                    .possible_values(ColorFormat::NAMES)
+                    .default_value("native")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name("CONTRAST")
                     .help("apply a post processing contrast filter")
@@ -90,11 +203,25 @@ impl Config<'static> {
                     .help("apply a post processing filter to turn colors greater than the specified value to white (255)")
                     .long("whitecutoff")
                     .takes_value(true),
+            ).arg(
+                Arg::with_name("REFRESH_POLICY")
+                    .help("when to use the fast and the full waveforms: text, balanced, image, low-wear, or the path of a policy file of key = value lines")
+                    .long("refresh-policy")
+                    .default_value("balanced")
+                    .validator(|value| refresh_policy(value).map(drop))
+                    .takes_value(true),
             ).arg(
                 Arg::with_name("ROTATE")
                     .help("rotation (1-4), tested on a Clara HD, try at own risk")
                     .long("rotate")
                     .takes_value(true),
+            ).arg(
+                Arg::with_name("AUTO_ROTATE")
+                    .help("rotate the screen with the device, on models with a G-sensor; locked starts held at --rotate.")
+                    .long("auto-rotate")
+                    .possible_values(AutoRotate::NAMES)
+                    .default_value("off")
+                    .takes_value(true),
             ).arg(
                 Arg::with_name("VIEW_ONLY")
                     .help("use VNC only as viewer, never sending any inputs?")
@@ -107,6 +234,117 @@ impl Config<'static> {
                     .default_value("/dev/input/event1")
                     .long("touch")
                     .takes_value(true),
+            ).arg(
+                Arg::with_name("KEYBOARD")
+                    .help("a keyboard device to type with, e.g. /dev/input/event3 for a USB OTG keyboard; repeat for several.")
+                    .long("keyboard")
+                    .takes_value(true)
+                    .multiple_occurrences(true),
+            ).arg(
+                Arg::with_name("KEYBOARD_LAYOUT")
+                    .help("the layout of the keyboards, translating their keys to the characters printed on them.")
+                    .long("keyboard-layout")
+                    .possible_values(KeyboardLayout::NAMES)
+                    .default_value("us")
+                    .takes_value(true),
+            ).arg(
+                Arg::with_name("TAP_SLOP")
+                    .help("how many millimeters a finger may move and still tap.")
+                    .default_value("2")
+                    .long("tap-slop")
+                    .takes_value(true),
+            ).arg(
+                Arg::with_name("LONG_PRESS")
+                    .help("how many milliseconds a finger is held still to right click.")
+                    .default_value("600")
+                    .long("long-press")
+                    .takes_value(true),
+            ).arg(
+                Arg::with_name("DOUBLE_TAP")
+                    .help("within how many milliseconds after a tap the finger comes down again to drag with the left button.")
+                    .default_value("300")
+                    .long("double-tap")
+                    .takes_value(true),
+            ).arg(
+                Arg::with_name("SCROLL_STEP")
+                    .help("how many millimeters two fingers move per scroll wheel click.")
+                    .default_value("5")
+                    .long("scroll-step")
+                    .takes_value(true),
+            ).arg(
+                Arg::with_name("STYLUS_PRESSURE")
+                    .help("the pressure below which the stylus touching the screen doesn't click.")
+                    .default_value("0")
+                    .long("stylus-pressure")
+                    .takes_value(true),
+            ).arg(
+                Arg::with_name("STYLUS_ERASE")
+                    .help("what the eraser button of the stylus does: left, middle, right, refresh, none or a key chord like ctrl+z.")
+                    .default_value("middle")
+                    .long("stylus-erase")
+                    .validator(|action| action.parse::<StylusAction>().map(drop))
+                    .takes_value(true),
+            ).arg(
+                Arg::with_name("STYLUS_HIGHLIGHT")
+                    .help("what the highlight button on the side of the stylus does: left, middle, right, refresh, none or a key chord like ctrl+z.")
+                    .default_value("right")
+                    .long("stylus-highlight")
+                    .validator(|action| action.parse::<StylusAction>().map(drop))
+                    .takes_value(true),
+            ).arg(
+                Arg::with_name("BUTTONS_INPUT")
+                    .help("the device that provides the power and page turn buttons, and the sleep cover [default: /dev/input/event0].")
+                    .long("buttons")
+                    .takes_value(true),
+            ).arg(
+                Arg::with_name("PAGE_BACKWARD")
+                    .help("the keys the page back button presses, e.g. pageup, left or ctrl+home.")
+                    .default_value("pageup")
+                    .long("page-backward")
+                    .validator(|keys| chord(keys).map(drop).ok_or("expected keys like pageup, left or ctrl+home"))
+                    .takes_value(true),
+            ).arg(
+                Arg::with_name("PAGE_FORWARD")
+                    .help("the keys the page forward button presses, e.g. pagedown, right or ctrl+end.")
+                    .default_value("pagedown")
+                    .long("page-forward")
+                    .validator(|keys| chord(keys).map(drop).ok_or("expected keys like pagedown, right or ctrl+end"))
+                    .takes_value(true),
+            ).arg(
+                Arg::with_name("PAGE_REFRESH")
+                    .help("fully refresh the screen once the page turned with the buttons is drawn, clearing ghosting.")
+                    .default_value("false")
+                    .long("page-refresh")
+                    .takes_value(true),
+            ).arg(
+                Arg::with_name("GYROSCOPE_INPUT")
+                    .help("the device that provides the G-sensor, if it's not the buttons device.")
+                    .long("gyroscope")
+                    .takes_value(true),
+            ).arg(
+                Arg::with_name("SLEEP_DISCONNECT")
+                    .help("disconnect while the cover is closed or the power button put the screen to sleep, reconnecting on wake up.")
+                    .default_value("false")
+                    .long("sleep-disconnect")
+                    .takes_value(true),
+            ).arg(
+                Arg::with_name("RECONNECT")
+                    .help("retry with an increasing delay when the connection fails or drops, instead of quitting.")
+                    .default_value("true")
+                    .long("reconnect")
+                    .takes_value(true),
+            ).arg(
+                Arg::with_name("RESIZE")
+                    .help("ask the server to resize its desktop to the screen of this device, also after rotating.")
+                    .default_value("false")
+                    .long("resize")
+                    .takes_value(true),
+            ).arg(
+                Arg::with_name("CONTINUOUS_UPDATES")
+                    .help("let the server push updates as they happen, paused while the screen refreshes, instead of polling 30 times a second.")
+                    .default_value("false")
+                    .long("continuous-updates")
+                    .takes_value(true),
             ).arg( // fake arg; making `cross run -- localhost` possible despite our always present arm release target.
                 Arg::with_name("target")
                     .long("target")
@@ -117,3 +355,13 @@ impl Config<'static> {
     }
 
 }
+
+/// The buttons device of Kobos, if there is one; a missing device only turns the buttons off.
+fn default_buttons_input() -> Option<String> {
+    if Path::new(DEFAULT_BUTTONS_INPUT).exists() {
+        Some(DEFAULT_BUTTONS_INPUT.to_string())
+    } else {
+        warn!("no buttons device at {}, the buttons and the sleep cover are off", DEFAULT_BUTTONS_INPUT);
+        None
+    }
+}
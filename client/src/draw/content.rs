@@ -0,0 +1,77 @@
+use display::geom::Rectangle;
+
+use crate::draw::pixmap::ReadonlyPixmap;
+
+/// At most this many pixels are looked at per rectangle, larger ones are sampled on a grid.
+const MAX_SAMPLES: u32 = 1 << 16;
+
+/// What a dirty rectangle shows, judging by the gray histogram of its processed pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Content {
+    /// Black and white only, which the A2 waveform draws without loss.
+    Binary,
+    /// Mostly black and white with some grays, like anti-aliased text.
+    Text,
+    /// Grays spread over the whole range, like photos, which need all 16 levels.
+    Photo,
+}
+
+/// Sorts the pixels of `rect`, clipped to `map`, into 16 gray levels and judges the content by them:
+/// binary when hardly any pixel is gray, a photo when a third of them are gray and spread over half the levels.
+pub fn classify(map: &ReadonlyPixmap, rect: &Rectangle) -> Content {
+    let (left, top) = (rect.min.x.max(0) as u32, rect.min.y.max(0) as u32);
+    let (right, bottom) = ((rect.max.x.max(0) as u32).min(map.width), (rect.max.y.max(0) as u32).min(map.height));
+    if left >= right || top >= bottom {
+        return Content::Binary;
+    }
+    let area = (right - left) * (bottom - top);
+    let step = ((area / MAX_SAMPLES) as f32).sqrt().ceil().max(1.0) as usize;
+
+    let mut histogram = [0u32; 16];
+    for y in (top..bottom).step_by(step) {
+        for x in (left..right).step_by(step) {
+            histogram[(map.get_pixel(x, y).gray() >> 4) as usize] += 1;
+        }
+    }
+    let total: u32 = histogram.iter().sum();
+    let grays = &histogram[1..15];
+    let gray: u32 = grays.iter().sum();
+
+    if gray * 100 <= total {
+        Content::Binary
+    } else if gray * 3 >= total && grays.iter().filter(|&&count| count * 200 >= total).count() >= 7 {
+        Content::Photo
+    } else {
+        Content::Text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use display::rect;
+
+    fn classify_rows(rows: &[Vec<u8>]) -> Content {
+        let data: Vec<u8> = rows.concat();
+        let map = ReadonlyPixmap { width: rows[0].len() as u32, height: rows.len() as u32, samples: 1, data: &data };
+        classify(&map, &rect![0, 0, map.width as i32, map.height as i32])
+    }
+
+    #[test]
+    fn tells_binary_text_and_photos_apart() {
+        let terminal: Vec<Vec<u8>> = (0..20).map(|y| (0..20).map(|x| if (x + y) % 3 == 0 { 0x00 } else { 0xff }).collect()).collect();
+        assert_eq!(classify_rows(&terminal), Content::Binary);
+
+        // glyphs with a gray fringe on a white page
+        let text: Vec<Vec<u8>> = (0..20)
+            .map(|y| (0..20).map(|x| match (x + y) % 8 { 0 => 0x00, 1 => 0x80, 2 => 0xc0, _ => 0xff }).collect())
+            .collect();
+        assert_eq!(classify_rows(&text), Content::Text);
+
+        let gradient: Vec<Vec<u8>> = (0..20).map(|_| (0..20).map(|x| (x * 255 / 19) as u8).collect()).collect();
+        assert_eq!(classify_rows(&gradient), Content::Photo);
+
+        // a flat gray panel is no photo
+        assert_eq!(classify_rows(&vec![vec![0xe0; 20]; 20]), Content::Text);
+    }
+}
@@ -0,0 +1,224 @@
+use display::framebuffer::{Framebuffer, UpdateMode};
+use display::geom::{Point, Rectangle, RegionSet};
+use display::{pt, rect};
+
+use crate::draw::pixmap::ReadonlyPixmap;
+use crate::draw::shadow::Shadow;
+use crate::draw::util;
+
+/// The pointer shape of the Cursor pseudo-encoding, drawn locally on top of the desktop.
+/// Moving the pointer then costs neither a server update nor more than two tiny refreshes.
+/// The `overlays` the methods take are the areas of the menu and on-screen keyboard, which
+/// stay on top: the cursor is neither drawn nor restored inside them.
+pub struct Cursor {
+    width: u32,
+    height: u32,
+    hotspot: Point,
+    /// Processed pixels, in the layout of `util::samples()`.
+    pixels: Vec<u8>,
+    /// One bit per pixel, rows padded to full bytes; set bits are opaque.
+    mask: Vec<u8>,
+    position: Option<Point>,
+    /// The screen area the cursor currently covers.
+    drawn: Option<Rectangle>,
+}
+
+/// A pointer shape as sent with `Event::SetCursor`, with processed pixels.
+pub struct CursorShape {
+    pub size: (u16, u16),
+    pub hotspot: (u16, u16),
+    pub pixels: Vec<u8>,
+    pub mask: Vec<u8>,
+}
+
+impl Cursor {
+    pub fn new() -> Cursor {
+        Cursor {
+            width: 0,
+            height: 0,
+            hotspot: pt!(0, 0),
+            pixels: Vec::new(),
+            mask: Vec::new(),
+            position: None,
+            drawn: None,
+        }
+    }
+
+    /// Replaces the shape, redrawing the cursor with it.
+    pub fn set_shape(&mut self, fb: &mut Box<dyn Framebuffer>, shadow: &Shadow, overlays: &[Rectangle], shape: CursorShape) {
+        let old = self.hide(fb, shadow, overlays);
+        self.keep_shape(shape);
+        let new = self.show(fb, shadow, overlays);
+        refresh(fb, old, new);
+    }
+
+    /// Replaces the shape without drawing it, for when the screen shows something else.
+    pub fn keep_shape(&mut self, shape: CursorShape) {
+        self.width = shape.size.0 as u32;
+        self.height = shape.size.1 as u32;
+        self.hotspot = pt!(shape.hotspot.0 as i32, shape.hotspot.1 as i32);
+        self.pixels = shape.pixels;
+        self.mask = shape.mask;
+    }
+
+    /// Moves the cursor to where the pointer was sent to, refreshing the old and new area.
+    pub fn move_to(&mut self, fb: &mut Box<dyn Framebuffer>, shadow: &Shadow, overlays: &[Rectangle], position: Point) {
+        if self.position == Some(position) {
+            return;
+        }
+        let old = self.hide(fb, shadow, overlays);
+        self.position = Some(position);
+        let new = self.show(fb, shadow, overlays);
+        refresh(fb, old, new);
+    }
+
+    /// Draws the cursor again, if `dirty` painted over it, so that the next update includes it.
+    pub fn redraw(&mut self, fb: &mut Box<dyn Framebuffer>, shadow: &Shadow, overlays: &[Rectangle], dirty: &[Rectangle]) {
+        if let Some(drawn) = self.drawn {
+            if dirty.iter().any(|rect| rect.overlaps(&drawn)) {
+                self.show(fb, shadow, overlays);
+            }
+        }
+    }
+
+    /// Forgets where the cursor was drawn, after the screen has been cleared.
+    pub fn invalidate(&mut self) {
+        self.drawn = None;
+    }
+
+    /// The part of the cursor both on the desktop and on the `screen`, which can be smaller.
+    fn area(&self, screen: &Rectangle, shadow: &Shadow) -> Option<Rectangle> {
+        let position = self.position?;
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+        let min = pt!(position.x - self.hotspot.x, position.y - self.hotspot.y);
+        let area = rect![min.x, min.y, min.x + self.width as i32, min.y + self.height as i32];
+        area.intersection(&rect![0, 0, shadow.width as i32, shadow.height as i32])?
+            .intersection(screen)
+    }
+
+    /// Restores the desktop below the cursor, apart from where an overlay covers it by now.
+    fn hide(&mut self, fb: &mut Box<dyn Framebuffer>, shadow: &Shadow, overlays: &[Rectangle]) -> Option<Rectangle> {
+        let drawn = self.drawn.take()?;
+        let mut below = RegionSet::new();
+        below.union(&drawn);
+        for overlay in overlays {
+            below.difference(overlay);
+        }
+        for rect in below.rectangles() {
+            shadow.draw(fb, &util::to_vnc_rect(rect));
+        }
+        Some(drawn)
+    }
+
+    fn show(&mut self, fb: &mut Box<dyn Framebuffer>, shadow: &Shadow, overlays: &[Rectangle]) -> Option<Rectangle> {
+        let area = self.area(&fb.rect(), shadow)?;
+        let shape = ReadonlyPixmap {
+            width: self.width,
+            height: self.height,
+            samples: shadow.samples,
+            data: &self.pixels,
+        };
+        let origin = pt!(self.position?.x - self.hotspot.x, self.position?.y - self.hotspot.y);
+        let stride = (self.width as usize).div_ceil(8);
+        for y in area.min.y..area.max.y {
+            for x in area.min.x..area.max.x {
+                let (u, v) = ((x - origin.x) as usize, (y - origin.y) as usize);
+                let opaque = self.mask.get(v * stride + u / 8).is_some_and(|bits| bits & (0x80 >> (u % 8)) != 0);
+                if opaque && !overlays.iter().any(|overlay| overlay.includes(pt!(x, y))) {
+                    fb.set_pixel(x as u32, y as u32, shape.get_pixel(u as u32, v as u32));
+                }
+            }
+        }
+        self.drawn = Some(area);
+        Some(area)
+    }
+}
+
+fn refresh(fb: &mut Box<dyn Framebuffer>, old: Option<Rectangle>, new: Option<Rectangle>) {
+    match (old, new) {
+        (Some(mut old), Some(new)) if old.overlaps(&new) => {
+            old.absorb(&new);
+            fb.update(&old, UpdateMode::FastMono).ok();
+        }
+        (old, new) => {
+            for rect in old.iter().chain(new.iter()) {
+                fb.update(rect, UpdateMode::FastMono).ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Error;
+    use display::color::Color;
+    use display::framebuffer::Pixmap;
+
+    /// A screen that fails on pixels outside of it, like the unchecked writes of the Kobo framebuffers.
+    struct Screen(Pixmap);
+
+    impl Framebuffer for Screen {
+        fn set_pixel(&mut self, x: u32, y: u32, color: Color) {
+            assert!(x < self.0.width && y < self.0.height, "pixel {},{} is off the screen", x, y);
+            self.0.set_pixel(x, y, color);
+        }
+        fn set_blended_pixel(&mut self, x: u32, y: u32, color: Color, alpha: f32) {
+            self.0.set_blended_pixel(x, y, color, alpha);
+        }
+        fn invert_region(&mut self, rect: &Rectangle) {
+            self.0.invert_region(rect);
+        }
+        fn shift_region(&mut self, rect: &Rectangle, drift: u8) {
+            self.0.shift_region(rect, drift);
+        }
+        fn update(&mut self, _rect: &Rectangle, _mode: UpdateMode) -> Result<u32, Error> {
+            Ok(1)
+        }
+        fn wait(&self, _token: u32) -> Result<i32, Error> {
+            Ok(1)
+        }
+        fn save(&self, path: &str) -> Result<(), Error> {
+            self.0.save(path)
+        }
+        fn set_rotation(&mut self, n: i8) -> Result<(u32, u32), Error> {
+            self.0.set_rotation(n)
+        }
+        fn set_monochrome(&mut self, _enable: bool) {}
+        fn set_dithered(&mut self, _enable: bool) {}
+        fn set_inverted(&mut self, _enable: bool) {}
+        fn monochrome(&self) -> bool {
+            false
+        }
+        fn dithered(&self) -> bool {
+            false
+        }
+        fn inverted(&self) -> bool {
+            false
+        }
+        fn width(&self) -> u32 {
+            self.0.width
+        }
+        fn height(&self) -> u32 {
+            self.0.height
+        }
+    }
+
+    #[test]
+    fn stays_on_a_screen_smaller_than_the_desktop() {
+        let mut fb: Box<dyn Framebuffer> = Box::new(Screen(Pixmap::new(10, 8, 1)));
+        let shadow = Shadow::new(16, 12, 1);
+        let mut cursor = Cursor::new();
+        let shape = CursorShape { size: (4, 4), hotspot: (0, 0), pixels: vec![0; 16], mask: vec![0xf0; 4] };
+        cursor.set_shape(&mut fb, &shadow, &[], shape);
+
+        cursor.move_to(&mut fb, &shadow, &[], pt!(8, 6));
+        assert_eq!(cursor.drawn, Some(rect![8, 6, 10, 8]), "clipped to the screen");
+        cursor.move_to(&mut fb, &shadow, &[], pt!(12, 9));
+        assert_eq!(cursor.drawn, None, "off the screen, on the desktop");
+        cursor.move_to(&mut fb, &shadow, &[], pt!(2, 2));
+        assert_eq!(cursor.drawn, Some(rect![2, 2, 6, 6]));
+    }
+}
@@ -1,91 +1,167 @@
 use display::device::CURRENT_DEVICE;
 use display::framebuffer::{Framebuffer, Pixmap, UpdateMode};
-use display::geom::Rectangle;
+use display::geom::{Rectangle, RegionSet};
 use display::rect;
 use std::time::Instant;
 use vnc::Rect;
 
-const MAX_DIRTY_REFRESHES: usize = 500;
-pub struct Draw {
+use crate::draw::content::{self, Content};
+use crate::draw::ghosting::Ghosting;
+use crate::draw::policy::RefreshPolicy;
+use crate::draw::shadow::Shadow;
+
+/// Updates are aligned to this many pixels, the steps the EPDC processes regions in.
+const UPDATE_GRANULARITY: i32 = 8;
+/// What starting an update costs, in pixels: nearby rects are merged while that adds fewer pixels.
+const UPDATE_COST: u32 = 64 * 64;
+
+pub struct Draw<'a> {
     pub dirty_rects: Vec<Rectangle>,
-    pub dirty_rects_since_refresh: Vec<Rectangle>,
     pub has_drawn_once: bool,
-    pub dirty_update_count: usize,
     pub time_at_last_draw: Instant,
+    /// The wear of the screen tiles since their last cleanup.
+    pub ghosting: Ghosting,
+    /// The token of the latest update started, which the panel is busy with until it completes.
+    pending_update: Option<u32>,
+    policy: &'a dyn RefreshPolicy,
 }
 
-impl Draw {
+impl<'a> Draw<'a> {
 
-    pub fn new() -> Draw {
+    pub fn new(policy: &'a dyn RefreshPolicy) -> Draw<'a> {
         return Draw {
             dirty_rects: Vec::<Rectangle>::new(),
-            dirty_rects_since_refresh: Vec::<Rectangle>::new(),
             has_drawn_once: false,
-            dirty_update_count: 0,
             time_at_last_draw: Instant::now(),
+            ghosting: Ghosting::new(),
+            pending_update: None,
+            policy,
         };
     }
 
     pub fn update(&mut self, fb: &mut Box<dyn Framebuffer>, fb_rect: Rectangle) {
         self.dirty_rects.clear();
-        self.dirty_rects_since_refresh.clear();
+        self.ghosting.fit(fb.rect());
+        let mode = if self.has_drawn_once { UpdateMode::Partial } else { self.policy.cleanup_mode() };
+        self.has_drawn_once = true;
         #[cfg(feature = "eink_device")]
         {
-            if !self.has_drawn_once || self.dirty_update_count > MAX_DIRTY_REFRESHES {
-                fb.update(&fb_rect, UpdateMode::Full).ok();
-                self.dirty_update_count = 0;
-                self.has_drawn_once = true;
-            } else {
-                fb.update(&fb_rect, UpdateMode::Partial).ok();
-            }
+            self.pending_update = fb.update(&fb_rect, mode).ok().or(self.pending_update);
         }
+        self.ghosting.record(&fb_rect, mode);
     }
 
+    /// Cleans up the tiles the policy deems worn.
     pub fn refresh(&mut self, fb: &mut Box<dyn Framebuffer>) {
-        for dr in &self.dirty_rects_since_refresh {
+        self.ghosting.fit(fb.rect());
+        for dr in self.take_cleanup() {
+            debug!("Cleaning up {:?}", dr);
+            #[cfg(feature = "eink_device")]
+            {
+                self.pending_update = fb.update(&dr, self.policy.cleanup_mode()).ok().or(self.pending_update);
+            }
+        }
+    }
+
+    /// The token of the latest update started since the last call, to wait for with `Framebuffer::wait`.
+    pub fn take_update(&mut self) -> Option<u32> {
+        self.pending_update.take()
+    }
+
+    /// Forgets the worn tiles, returning the rectangles to clean up.
+    pub fn take_cleanup(&mut self) -> Vec<Rectangle> {
+        let policy = self.policy;
+        self.ghosting.take(|wear| policy.cleanup_due(wear))
+    }
+
+    /// Whether the policy wants worn tiles cleaned up, now that the screen is still.
+    pub fn idle_refresh_due(&self) -> bool {
+        self.policy.idle_cleanup_due(self.time_at_last_draw.elapsed()) && self.ghosting.any(|wear| self.policy.cleanup_due(wear))
+    }
+
+    /// Redraws the whole screen with the full waveform, clearing any ghosting.
+    pub fn full_refresh(&mut self, fb: &mut Box<dyn Framebuffer>) {
+        #[cfg(feature = "eink_device")]
+        {
+            self.pending_update = fb.update(&fb.rect(), UpdateMode::Full).ok().or(self.pending_update);
+        }
+        self.ghosting.clear();
+    }
+
+    /// Updates the dirty rects, with waveforms that suit what the processed pixels in `shadow` show.
+    pub fn draw_end(&mut self, fb: &mut Box<dyn Framebuffer>, shadow: &Shadow) {
+        self.ghosting.fit(fb.rect());
+        let map = shadow.pixmap();
+        for (dr, mode) in self.end_frame(|dr| content::classify(&map, dr)) {
+            debug!("Updating dirty rect {:?} with {:?}", dr, mode);
+
             #[cfg(feature = "eink_device")]
             {
-                fb.update(&dr, UpdateMode::Full).ok();
+                self.pending_update = fb.update(&dr, mode).ok().or(self.pending_update);
             }
         }
-        self.dirty_update_count = 0;
-        self.dirty_rects_since_refresh.clear();
     }
 
-    pub fn draw_end(&mut self, fb: &mut Box<dyn Framebuffer>) {
+    /// Ends the frame of `dirty_rects`, returning the batch of updates to commit for it. The policy
+    /// picks the waveform of each rect, judged by its `content`; rects sharing a waveform are coalesced
+    /// into few updates, and what the update of a slower waveform covers is left to it. The slowest waveforms
+    /// come first, so they start early and the whole batch settles at once. The tiles they cover
+    /// wear accordingly.
+    pub fn end_frame<F: Fn(&Rectangle) -> Content>(&mut self, content: F) -> Vec<(Rectangle, UpdateMode)> {
         if !self.has_drawn_once {
-            self.has_drawn_once = self.dirty_rects.len() > 0;
+            self.has_drawn_once = !self.dirty_rects.is_empty();
         }
-    
-        self.dirty_update_count += 1;
-    
-        if self.dirty_update_count > MAX_DIRTY_REFRESHES {
-            info!("Full refresh!");
-            self.refresh(fb);
-        } else {
-            for dr in &self.dirty_rects {
-                debug!("Updating dirty rect {:?}", dr);
-    
-                #[cfg(feature = "eink_device")]
-                {
-                    if dr.height() < 100 && dr.width() < 100 {
-                        debug!("Fast mono update!");
-                        fb.update(&dr, UpdateMode::FastMono).ok();
-                    } else {
-                        fb.update(&dr, UpdateMode::Partial).ok();
-                    }
+        self.time_at_last_draw = Instant::now();
+
+        let mut batches: Vec<(UpdateMode, RegionSet)> = Vec::new();
+        for dr in self.dirty_rects.drain(..) {
+            let mode = self.policy.mode(&dr, content(&dr));
+            match batches.iter_mut().find(|(batch, _)| *batch == mode) {
+                Some((_, region)) => region.union(&dr),
+                None => {
+                    let mut region = RegionSet::new();
+                    region.union(&dr);
+                    batches.push((mode, region));
                 }
-    
-                push_to_dirty_rect_list(&mut self.dirty_rects_since_refresh, *dr);
             }
-    
-            self.time_at_last_draw = Instant::now();
         }
-        self.dirty_rects.clear();
+        batches.sort_by_key(|(mode, _)| waveform_order(*mode));
+
+        let bounds = self.ghosting.bounds();
+        let mut updates: Vec<(Rectangle, UpdateMode)> = Vec::new();
+        for (mode, mut region) in batches {
+            for (planned, _) in &updates {
+                region.difference(planned);
+            }
+            // merging may reach into the updates of slower waveforms again, which keep those pixels
+            let mut coalesced = RegionSet::new();
+            for dr in region.coalesce(&bounds, UPDATE_GRANULARITY, UPDATE_COST) {
+                coalesced.union(&dr);
+            }
+            for (planned, _) in &updates {
+                coalesced.difference(planned);
+            }
+            updates.extend(coalesced.rectangles().iter().map(|dr| (*dr, mode)));
+        }
+        for (dr, mode) in &updates {
+            self.ghosting.record(dr, *mode);
+        }
+        updates
     }
 
 }
 
+/// Orders the waveforms from the slowest to the fastest.
+fn waveform_order(mode: UpdateMode) -> u8 {
+    match mode {
+        UpdateMode::Full => 0,
+        UpdateMode::Partial => 1,
+        UpdateMode::Gui => 2,
+        UpdateMode::Fast => 3,
+        UpdateMode::FastMono => 4,
+    }
+}
+
 pub fn push_to_dirty_rect_list(list: &mut Vec<Rectangle>, rect: Rectangle) {
     for dr in list.iter_mut() {
         if dr.contains(&rect) {
@@ -0,0 +1,132 @@
+use display::framebuffer::UpdateMode;
+use display::geom::Rectangle;
+use display::rect;
+use fxhash::FxHashMap;
+
+use crate::draw::push_to_dirty_rect_list;
+
+/// The screen is split into square tiles of this many pixels.
+pub const TILE_SIZE: i32 = 64;
+
+/// The updates a tile received since it was last drawn with the full waveform.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct TileWear {
+    /// Updates with the fast two level waveform (A2).
+    pub fast: u32,
+    /// Updates with the fast waveform forced to black and white.
+    pub fast_mono: u32,
+    /// Updates with the gray waveforms that don't flash (GL16, GLR16).
+    pub partial: u32,
+}
+
+impl TileWear {
+    /// A rough measure of the ghosting, where the two level waveforms leave much more of it than the gray ones.
+    pub fn ghosting(&self) -> u32 {
+        4 * (self.fast + self.fast_mono) + self.partial
+    }
+
+    fn add(&mut self, mode: UpdateMode) {
+        match mode {
+            UpdateMode::Fast => self.fast += 1,
+            UpdateMode::FastMono => self.fast_mono += 1,
+            UpdateMode::Gui | UpdateMode::Partial => self.partial += 1,
+            UpdateMode::Full => (),
+        }
+    }
+}
+
+/// Keeps the wear of the tiles of the screen, so cleanups only flash the tiles that need one.
+/// Only worn tiles are stored; a tile drawn with the full waveform is as good as new.
+#[derive(Debug)]
+pub struct Ghosting {
+    bounds: Rectangle,
+    tiles: FxHashMap<(i32, i32), TileWear>,
+}
+
+impl Ghosting {
+    pub fn new() -> Ghosting {
+        Ghosting {
+            bounds: rect![0, 0, 0, 0],
+            tiles: FxHashMap::default(),
+        }
+    }
+
+    /// Sets the screen the tiles cover, forgetting all wear when it changed, e.g. after a rotation.
+    pub fn fit(&mut self, bounds: Rectangle) {
+        if bounds != self.bounds {
+            self.bounds = bounds;
+            self.tiles.clear();
+        }
+    }
+
+    pub fn bounds(&self) -> Rectangle {
+        self.bounds
+    }
+
+    pub fn clear(&mut self) {
+        self.tiles.clear();
+    }
+
+    /// Records an update of `rect`; the full waveform clears the tiles it covers completely.
+    pub fn record(&mut self, rect: &Rectangle, mode: UpdateMode) {
+        let Some(rect) = rect.intersection(&self.bounds) else { return };
+        for y in rect.min.y.div_euclid(TILE_SIZE)..=(rect.max.y - 1).div_euclid(TILE_SIZE) {
+            for x in rect.min.x.div_euclid(TILE_SIZE)..=(rect.max.x - 1).div_euclid(TILE_SIZE) {
+                if mode != UpdateMode::Full {
+                    self.tiles.entry((x, y)).or_default().add(mode);
+                } else if self.tile(x, y).is_some_and(|tile| rect.contains(&tile)) {
+                    self.tiles.remove(&(x, y));
+                }
+            }
+        }
+    }
+
+    /// Whether `due` wants any tile cleaned up.
+    pub fn any<F: Fn(&TileWear) -> bool>(&self, due: F) -> bool {
+        self.tiles.values().any(due)
+    }
+
+    /// Forgets the tiles `due` wants cleaned up, returning them merged into rectangles.
+    pub fn take<F: Fn(&TileWear) -> bool>(&mut self, due: F) -> Vec<Rectangle> {
+        let mut keys: Vec<(i32, i32)> = self.tiles.iter().filter(|(_, wear)| due(wear)).map(|(key, _)| *key).collect();
+        keys.sort_by_key(|&(x, y)| (y, x));
+        let mut rects = Vec::new();
+        for (x, y) in keys {
+            self.tiles.remove(&(x, y));
+            if let Some(tile) = self.tile(x, y) {
+                push_to_dirty_rect_list(&mut rects, tile);
+            }
+        }
+        rects
+    }
+
+    /// The part of the tile at column `x` and row `y` on the screen.
+    fn tile(&self, x: i32, y: i32) -> Option<Rectangle> {
+        let (left, top) = (x * TILE_SIZE, y * TILE_SIZE);
+        rect![left, top, left + TILE_SIZE, top + TILE_SIZE].intersection(&self.bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cleans_up_worn_tiles_only() {
+        let mut ghosting = Ghosting::new();
+        ghosting.fit(rect![0, 0, 200, 100]);
+        let due = |wear: &TileWear| wear.ghosting() >= 8;
+
+        ghosting.record(&rect![10, 10, 20, 20], UpdateMode::FastMono);
+        ghosting.record(&rect![70, 10, 80, 20], UpdateMode::Fast);
+        ghosting.record(&rect![150, 70, 200, 100], UpdateMode::Partial);
+        assert!(!ghosting.any(due));
+
+        ghosting.record(&rect![10, 10, 70, 20], UpdateMode::FastMono);
+        ghosting.record(&rect![150, 70, 200, 100], UpdateMode::FastMono);
+        ghosting.record(&rect![128, 64, 200, 100], UpdateMode::Full);
+        // the two worn neighbours are cleaned up at once, the tile at the bottom right edge is clean again
+        assert_eq!(ghosting.take(due), vec![rect![0, 0, 128, 64]]);
+        assert!(ghosting.take(due).is_empty());
+    }
+}
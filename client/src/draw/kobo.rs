@@ -2,24 +2,25 @@ use display::device::CURRENT_DEVICE;
 use display::framebuffer::{Framebuffer, KoboFramebuffer1, KoboFramebuffer2, Pixmap};
 
 use crate::draw::pixmap::ReadonlyPixmap;
+use crate::error::Error;
 use vnc::Rect;
 
 use anyhow::Context;
 
 const FB_DEVICE: &str = "/dev/fb0";
 
-pub fn new_frame_buffer(rotate: i8) -> Box<dyn Framebuffer> {
+pub fn new_frame_buffer(rotate: i8) -> Result<Box<dyn Framebuffer>, Error> {
     let mut fb: Box<dyn Framebuffer> = if CURRENT_DEVICE.mark() != 8 {
         Box::new(
             KoboFramebuffer1::new(FB_DEVICE)
                 .context("can't create framebuffer")
-                .unwrap(),
+                .map_err(Error::Framebuffer)?,
         )
     } else {
         Box::new(
             KoboFramebuffer2::new(FB_DEVICE)
                 .context("can't create framebuffer")
-                .unwrap(),
+                .map_err(Error::Framebuffer)?,
         )
     };
 
@@ -28,7 +29,7 @@ pub fn new_frame_buffer(rotate: i8) -> Box<dyn Framebuffer> {
         fb.set_rotation(rotate).ok();
     }
 
-    fb
+    Ok(fb)
 }
 
 pub fn set_pixel_map_ro(fb: &mut Box<dyn Framebuffer>, delta: &MapDelta, pixmap: &ReadonlyPixmap) {
@@ -1,10 +1,20 @@
 #![allow(unused)]
 
 mod pixmap;
+mod content;
 mod draw;
+mod ghosting;
+mod policy;
 
+pub mod cursor;
 pub mod kobo;
+pub mod shadow;
+pub mod status;
+pub mod text;
 pub mod util;
 
 pub use self::pixmap::ReadonlyPixmap;
 pub use self::draw::{Draw, push_to_dirty_rect_list};
+pub use self::content::Content;
+pub use self::ghosting::TileWear;
+pub use self::policy::{refresh_policy, RefreshPolicy, RefreshPreset};
@@ -1,6 +1,7 @@
 use display::color::{Color, WHITE};
 pub const RED: Color = Color::Rgb(255, 0, 0);
 
+/// Pixels as produced by `processing::streamline_pixel_color`: one gray byte, or BGRx on color devices.
 #[derive(Debug, Clone)]
 pub struct ReadonlyPixmap<'a> {
     pub width: u32,
@@ -18,7 +19,7 @@ impl<'a> ReadonlyPixmap<'a> {
         let addr = self.samples * (y * self.width + x) as usize;
         let max=self.data.len();
         if self.samples == 1 {
-            if max < addr {
+            if max <= addr {
                 return RED; // signal an invalid pixel request
             }
             let col = self.data[addr];
@@ -0,0 +1,286 @@
+use std::fs;
+use std::str::FromStr;
+use std::time::Duration;
+
+use display::framebuffer::UpdateMode;
+use display::geom::Rectangle;
+
+use crate::draw::content::Content;
+use crate::draw::ghosting::TileWear;
+
+/// Decides how the screen refreshes: the waveform of each dirty rectangle, and which tiles of the
+/// screen get the ghosting left by the other waveforms cleaned up by redrawing with the full one.
+/// `Draw` keeps the history, so a policy only has to look at what it is handed.
+pub trait RefreshPolicy {
+    /// The waveform for a dirty `rect` of a frame, showing `content`.
+    fn mode(&self, rect: &Rectangle, content: Content) -> UpdateMode;
+
+    /// Whether a tile with `wear` needs a cleanup.
+    fn cleanup_due(&self, wear: &TileWear) -> bool;
+
+    /// Whether the tiles that need one are cleaned up, once the screen has been still for `idle`.
+    fn idle_cleanup_due(&self, idle: Duration) -> bool;
+
+    /// The waveform of cleanups.
+    fn cleanup_mode(&self) -> UpdateMode {
+        UpdateMode::Full
+    }
+}
+
+/// A refresh policy made of thresholds, which covers the presets and policy files.
+/// Black and white content always gets the fast monochrome waveform, which draws it without loss.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RefreshPreset {
+    /// Rectangles narrower and lower than this get the fast monochrome waveform, unless they show a photo.
+    pub fast_mono_below: (u32, u32),
+    /// The waveform of the other rectangles.
+    pub large: UpdateMode,
+    /// The waveform of photos.
+    pub photo: UpdateMode,
+    /// The `TileWear::ghosting` at which a tile needs a cleanup.
+    pub cleanup_ghosting: u32,
+    /// How long the screen has to be still for a cleanup, or `None` to never clean up when idle.
+    pub idle_cleanup: Option<Duration>,
+}
+
+impl RefreshPreset {
+    pub const NAMES: [&'static str; 4] = ["text", "balanced", "image", "low-wear"];
+
+    /// Fast monochrome updates for typing and scrolling, cleaned up soon after.
+    pub const TEXT: RefreshPreset = RefreshPreset {
+        fast_mono_below: (300, 300),
+        large: UpdateMode::Partial,
+        photo: UpdateMode::Full,
+        cleanup_ghosting: 100,
+        idle_cleanup: Some(Duration::from_secs(2)),
+    };
+
+    pub const BALANCED: RefreshPreset = RefreshPreset {
+        fast_mono_below: (100, 100),
+        large: UpdateMode::Partial,
+        photo: UpdateMode::Full,
+        cleanup_ghosting: 40,
+        idle_cleanup: Some(Duration::from_secs(3)),
+    };
+
+    /// No monochrome waveform for small updates either, since it posterizes grays, and cleanups of any fast update.
+    pub const IMAGE: RefreshPreset = RefreshPreset {
+        fast_mono_below: (0, 0),
+        large: UpdateMode::Partial,
+        photo: UpdateMode::Full,
+        cleanup_ghosting: 4,
+        idle_cleanup: Some(Duration::from_secs(1)),
+    };
+
+    /// As few flashing cleanups as possible, which wear the panel and the battery.
+    pub const LOW_WEAR: RefreshPreset = RefreshPreset {
+        fast_mono_below: (100, 100),
+        large: UpdateMode::Partial,
+        photo: UpdateMode::Partial,
+        cleanup_ghosting: 400,
+        idle_cleanup: Some(Duration::from_secs(30)),
+    };
+
+    /// Reads a policy file of `key = value` lines, starting from the `preset` key or the balanced
+    /// preset, e.g.:
+    ///
+    /// ```text
+    /// preset = text
+    /// # 0x0 only uses the monochrome waveform for black and white content
+    /// fast_mono_below = 200x100
+    /// large = partial
+    /// photo = full
+    /// cleanup_ghosting = 60
+    /// idle_cleanup_ms = never
+    /// ```
+    pub fn from_file(path: &str) -> Result<RefreshPreset, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("can't read refresh policy {}: {}", path, err))?;
+        RefreshPreset::parse(&text).map_err(|err| format!("{}: {}", path, err))
+    }
+
+    fn parse(text: &str) -> Result<RefreshPreset, String> {
+        let mut policy = RefreshPreset::BALANCED;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |what: &str| format!("line {}: {} in '{}'", number + 1, what, line);
+            let (key, value) = line.split_once('=').ok_or_else(|| error("expected key = value"))?;
+            let value = value.trim();
+            match key.trim() {
+                "preset" => policy = value.parse().map_err(|err: String| error(&err))?,
+                "fast_mono_below" => {
+                    let size = value.split_once('x').and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)));
+                    policy.fast_mono_below = size.ok_or_else(|| error("expected a size like 100x100"))?;
+                }
+                "large" => policy.large = update_mode(value).ok_or_else(|| error("unknown update mode"))?,
+                "photo" => policy.photo = update_mode(value).ok_or_else(|| error("unknown update mode"))?,
+                "cleanup_ghosting" => policy.cleanup_ghosting = value.parse().map_err(|_| error("expected a number"))?,
+                "idle_cleanup_ms" => {
+                    let ms = never_or(value).ok_or_else(|| error("expected a number or never"))?;
+                    policy.idle_cleanup = ms.map(Duration::from_millis);
+                }
+                _ => return Err(error("unknown key")),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+impl FromStr for RefreshPreset {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<RefreshPreset, String> {
+        match name {
+            "text" => Ok(RefreshPreset::TEXT),
+            "balanced" => Ok(RefreshPreset::BALANCED),
+            "image" => Ok(RefreshPreset::IMAGE),
+            "low-wear" => Ok(RefreshPreset::LOW_WEAR),
+            _ => Err(format!("unknown refresh preset '{}', expected one of {}", name, RefreshPreset::NAMES.join(", "))),
+        }
+    }
+}
+
+impl RefreshPolicy for RefreshPreset {
+    fn mode(&self, rect: &Rectangle, content: Content) -> UpdateMode {
+        match content {
+            Content::Binary => UpdateMode::FastMono,
+            Content::Photo => self.photo,
+            Content::Text if rect.width() < self.fast_mono_below.0 && rect.height() < self.fast_mono_below.1 => UpdateMode::FastMono,
+            Content::Text => self.large,
+        }
+    }
+
+    fn cleanup_due(&self, wear: &TileWear) -> bool {
+        wear.ghosting() >= self.cleanup_ghosting
+    }
+
+    fn idle_cleanup_due(&self, idle: Duration) -> bool {
+        self.idle_cleanup.is_some_and(|after| idle > after)
+    }
+}
+
+/// The policy of the `--refresh-policy` argument: a preset name, or the path of a policy file.
+pub fn refresh_policy(value: &str) -> Result<Box<dyn RefreshPolicy>, String> {
+    if RefreshPreset::NAMES.contains(&value) {
+        return value.parse().map(|preset: RefreshPreset| Box::new(preset) as Box<dyn RefreshPolicy>);
+    }
+    RefreshPreset::from_file(value).map(|preset| Box::new(preset) as Box<dyn RefreshPolicy>)
+}
+
+fn update_mode(name: &str) -> Option<UpdateMode> {
+    match name {
+        "gui" => Some(UpdateMode::Gui),
+        "partial" => Some(UpdateMode::Partial),
+        "full" => Some(UpdateMode::Full),
+        "fast" => Some(UpdateMode::Fast),
+        "fast-mono" => Some(UpdateMode::FastMono),
+        _ => None,
+    }
+}
+
+fn never_or<T: FromStr>(value: &str) -> Option<Option<T>> {
+    if value == "never" {
+        Some(None)
+    } else {
+        value.parse().ok().map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::draw::Draw;
+    use display::rect;
+
+    /// Replays frames of dirty rectangles and their content through `Draw` on a 400x600 screen,
+    /// returning the updates of each frame.
+    fn replay<'a>(policy: &'a dyn RefreshPolicy, frames: &[Vec<(Rectangle, Content)>]) -> (Draw<'a>, Vec<Vec<(Rectangle, UpdateMode)>>) {
+        let mut draw = Draw::new(policy);
+        draw.ghosting.fit(rect![0, 0, 400, 600]);
+        let updates = frames
+            .iter()
+            .map(|frame| {
+                draw.dirty_rects.extend(frame.iter().map(|(rect, _)| *rect));
+                draw.end_frame(|rect| frame.iter().find(|(dirty, _)| dirty == rect).unwrap().1)
+            })
+            .collect();
+        (draw, updates)
+    }
+
+    #[test]
+    fn presets_pick_waveforms_and_clean_up() {
+        let typing = rect![16, 320, 32, 344];
+        let photo = rect![0, 0, 400, 304];
+        let page = rect![0, 304, 400, 600];
+        let icon = rect![328, 8, 352, 32];
+        let mut frames = vec![vec![(typing, Content::Text), (photo, Content::Text)]];
+        frames.extend((0..10).map(|_| vec![(icon, Content::Binary)]));
+
+        let (mut balanced, updates) = replay(&RefreshPreset::BALANCED, &frames);
+        assert_eq!(updates[0], vec![(photo, UpdateMode::Partial), (typing, UpdateMode::FastMono)]);
+        assert_eq!(updates[10], vec![(icon, UpdateMode::FastMono)]);
+        // the blinking icon only wore its own tile
+        assert_eq!(balanced.take_cleanup(), vec![rect![320, 0, 384, 64]]);
+        assert!(balanced.take_cleanup().is_empty());
+
+        let (_, updates) = replay(&RefreshPreset::IMAGE, &frames);
+        assert_eq!(updates[0], vec![(photo, UpdateMode::Partial), (typing, UpdateMode::Partial)], "too far apart to merge");
+
+        let (mut low_wear, _) = replay(&RefreshPreset::LOW_WEAR, &frames);
+        assert!(low_wear.take_cleanup().is_empty());
+        assert!(!RefreshPreset::LOW_WEAR.idle_cleanup_due(Duration::from_secs(10)));
+        assert!(RefreshPreset::TEXT.idle_cleanup_due(Duration::from_secs(3)));
+
+        frames.push(vec![(typing, Content::Photo), (photo, Content::Photo), (page, Content::Binary)]);
+        let (mut scrolled, updates) = replay(&RefreshPreset::BALANCED, &frames);
+        // the page is updated around the typing, which the full waveform updates already
+        assert_eq!(updates[11], vec![
+            (photo, UpdateMode::Full),
+            (typing, UpdateMode::Full),
+            (rect![0, 304, 400, 320], UpdateMode::FastMono),
+            (rect![0, 344, 400, 600], UpdateMode::FastMono),
+            (rect![0, 320, 16, 344], UpdateMode::FastMono),
+            (rect![32, 320, 400, 344], UpdateMode::FastMono),
+        ]);
+        assert!(scrolled.take_cleanup().is_empty(), "redrawn with the full waveform");
+    }
+
+    #[test]
+    fn frames_are_merged_and_ordered_by_waveform() {
+        let (left, right) = (rect![0, 0, 96, 200], rect![96, 0, 200, 200]);
+        let caret = rect![48, 48, 50, 72];
+        let icon = rect![304, 8, 320, 32];
+        let frame = vec![(icon, Content::Binary), (caret, Content::Binary), (left, Content::Text), (right, Content::Photo)];
+        let (_, updates) = replay(&RefreshPreset::IMAGE, &[frame]);
+        assert_eq!(updates[0], vec![(right, UpdateMode::Full), (left, UpdateMode::Partial), (icon, UpdateMode::FastMono)]);
+
+        let (_, updates) = replay(&RefreshPreset::IMAGE, &[vec![(left, Content::Text), (right, Content::Text)]]);
+        assert_eq!(updates[0], vec![(rect![0, 0, 200, 200], UpdateMode::Partial)]);
+
+        // scattered keystrokes end up in one update aligned to the hardware
+        let keys: Vec<(Rectangle, Content)> = (0..4).map(|i| (rect![10 + 20 * i, 100, 20 + 20 * i, 110], Content::Binary)).collect();
+        let (_, updates) = replay(&RefreshPreset::TEXT, &[keys]);
+        assert_eq!(updates[0], vec![(rect![8, 96, 80, 112], UpdateMode::FastMono)]);
+    }
+
+    #[test]
+    fn parses_policy_files() {
+        let policy = RefreshPreset::parse("preset = low-wear\n# comment\n\nfast_mono_below = 200x50\nlarge = fast\ncleanup_ghosting = 20\n").unwrap();
+        assert_eq!(
+            policy,
+            RefreshPreset {
+                fast_mono_below: (200, 50),
+                large: UpdateMode::Fast,
+                photo: UpdateMode::Partial,
+                cleanup_ghosting: 20,
+                idle_cleanup: Some(Duration::from_secs(30)),
+            }
+        );
+        assert_eq!(RefreshPreset::parse("idle_cleanup_ms = never").unwrap().idle_cleanup, None);
+        assert!(RefreshPreset::parse("cleanup_ghosting = often").is_err());
+        assert!(RefreshPreset::parse("speed = 11").is_err());
+        assert!("sepia".parse::<RefreshPreset>().is_err());
+    }
+}
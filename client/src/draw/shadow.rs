@@ -0,0 +1,187 @@
+use display::framebuffer::Framebuffer;
+use vnc::Rect;
+
+use crate::draw::pixmap::ReadonlyPixmap;
+
+/// Client side copy of the remote desktop, holding the pixels after post processing,
+/// in the layout `util::to_map` expects. Framebuffers can't be read back reliably,
+/// so `CopyRect` is resolved within this copy instead.
+pub struct Shadow {
+    pub width: u32,
+    pub height: u32,
+    pub samples: usize,
+    pub data: Vec<u8>,
+    /// Whether `data` matches the screen; false until the first complete frame was received.
+    pub synced: bool,
+}
+
+impl Shadow {
+    pub fn new(width: u16, height: u16, samples: usize) -> Shadow {
+        let (width, height) = (width as u32, height as u32);
+        Shadow {
+            width,
+            height,
+            samples,
+            data: vec![0xff; samples * (width * height) as usize],
+            synced: false,
+        }
+    }
+
+    fn fits(&self, rect: &Rect) -> bool {
+        rect.left as u32 + rect.width as u32 <= self.width
+            && rect.top as u32 + rect.height as u32 <= self.height
+    }
+
+    fn row_range(&self, left: u16, top: u32, width: u16) -> std::ops::Range<usize> {
+        let start = self.samples * (top * self.width + left as u32) as usize;
+        start..start + self.samples * width as usize
+    }
+
+    /// Stores the processed `pixels` of `rect` and returns the bounding box of the pixels that changed,
+    /// or `None` if the update is a no-op. Until `synced`, the whole `rect` counts as changed.
+    pub fn put(&mut self, rect: &Rect, pixels: &[u8]) -> Option<Rect> {
+        if rect.width == 0 || rect.height == 0 {
+            return None; // some servers send empty updates
+        }
+        let samples = self.samples;
+        let row_len = samples * rect.width as usize;
+        if !self.fits(rect) || pixels.len() < row_len * rect.height as usize {
+            warn!("ignoring pixels outside of the {}x{} desktop: {:?}", self.width, self.height, rect);
+            return None;
+        }
+        let mut changed: Option<(u16, u16, u16, u16)> = None;
+        for (y, row) in pixels.chunks_exact(row_len).take(rect.height as usize).enumerate() {
+            let range = self.row_range(rect.left, rect.top as u32 + y as u32, rect.width);
+            let old = &mut self.data[range];
+            let differs = |x: &usize| old[x * samples..(x + 1) * samples] != row[x * samples..(x + 1) * samples];
+            let first = (0..rect.width as usize).find(differs);
+            if let Some(first) = first {
+                let last = (first..rect.width as usize).rev().find(differs).unwrap_or(first);
+                let (first, last, y) = (first as u16, last as u16, y as u16);
+                changed = Some(match changed {
+                    Some((left, top, right, _)) => (left.min(first), top, right.max(last), y),
+                    None => (first, y, last, y),
+                });
+                old.copy_from_slice(row);
+            }
+        }
+        if !self.synced {
+            return Some(*rect);
+        }
+        changed.map(|(left, top, right, bottom)| Rect {
+            left: rect.left + left,
+            top: rect.top + top,
+            width: right - left + 1,
+            height: bottom - top + 1,
+        })
+    }
+
+    /// Copies the area at the top left corner of `src`, with the size of `dst`, onto `dst`.
+    /// Overlapping areas are copied as if through a temporary buffer.
+    pub fn copy(&mut self, src: &Rect, dst: &Rect) -> bool {
+        let src = Rect { left: src.left, top: src.top, width: dst.width, height: dst.height };
+        if !self.fits(&src) || !self.fits(dst) {
+            warn!("ignoring copy outside of the {}x{} desktop: {:?} -> {:?}", self.width, self.height, src, dst);
+            return false;
+        }
+        let height = dst.height as u32;
+        for row in 0..height {
+            // moving down, start with the bottom row so that no source row is overwritten before it was copied
+            let y = if dst.top > src.top { height - 1 - row } else { row };
+            let from = self.row_range(src.left, src.top as u32 + y, src.width);
+            let to = self.row_range(dst.left, dst.top as u32 + y, dst.width);
+            self.data.copy_within(from, to.start);
+        }
+        true
+    }
+
+    pub fn pixmap(&self) -> ReadonlyPixmap<'_> {
+        ReadonlyPixmap {
+            width: self.width,
+            height: self.height,
+            samples: self.samples,
+            data: &self.data,
+        }
+    }
+
+    /// Pushes the pixels of `rect` to the framebuffer, without updating the screen. Only the part
+    /// on the screen is drawn, as the desktop can be larger, e.g. after a rotation until it is resized.
+    pub fn draw(&self, fb: &mut Box<dyn Framebuffer>, rect: &Rect) {
+        let map = self.pixmap();
+        let screen = fb.rect();
+        let right = (rect.left as u32 + rect.width as u32).min(self.width).min(screen.max.x.max(0) as u32);
+        let bottom = (rect.top as u32 + rect.height as u32).min(self.height).min(screen.max.y.max(0) as u32);
+        for y in rect.top as u32..bottom {
+            for x in rect.left as u32..right {
+                fb.set_pixel(x, y, map.get_pixel(x, y));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(left: u16, top: u16, width: u16, height: u16) -> Rect {
+        Rect { left, top, width, height }
+    }
+
+    fn rows(shadow: &Shadow) -> Vec<Vec<u8>> {
+        shadow.data.chunks(shadow.width as usize).map(|row| row.to_vec()).collect()
+    }
+
+    #[test]
+    fn copy_overlapping_areas() {
+        let mut shadow = Shadow::new(4, 4, 1);
+        let pixels: Vec<u8> = (0..16).collect();
+        shadow.put(&rect(0, 0, 4, 4), &pixels);
+
+        assert!(shadow.copy(&rect(0, 0, 0, 0), &rect(1, 1, 3, 3)), "scroll down right");
+        assert_eq!(rows(&shadow), vec![
+            vec![0, 1, 2, 3],
+            vec![4, 0, 1, 2],
+            vec![8, 4, 5, 6],
+            vec![12, 8, 9, 10],
+        ]);
+
+        assert!(shadow.copy(&rect(1, 1, 0, 0), &rect(0, 0, 3, 3)), "scroll up left");
+        assert_eq!(rows(&shadow), vec![
+            vec![0, 1, 2, 3],
+            vec![4, 5, 6, 2],
+            vec![8, 9, 10, 6],
+            vec![12, 8, 9, 10],
+        ]);
+
+        assert!(!shadow.copy(&rect(2, 2, 0, 0), &rect(3, 3, 2, 2)), "out of bounds");
+    }
+
+    #[test]
+    fn put_reports_changed_pixels() {
+        let mut shadow = Shadow::new(4, 4, 1);
+        assert_eq!(shadow.put(&rect(0, 0, 2, 2), &[0xff; 4]), Some(rect(0, 0, 2, 2)), "unknown screen contents");
+        shadow.synced = true;
+
+        assert_eq!(shadow.put(&rect(0, 0, 4, 4), &[0xff; 16]), None, "nothing changed");
+        let mut pixels = [0xff; 16];
+        pixels[6] = 0;
+        pixels[9] = 0;
+        assert_eq!(shadow.put(&rect(0, 0, 4, 4), &pixels), Some(rect(1, 1, 2, 2)), "bounding box");
+        assert_eq!(shadow.put(&rect(1, 2, 3, 2), &[0, 0xff, 0xff, 0xff, 0xff, 0]), Some(rect(3, 3, 1, 1)), "offset");
+    }
+
+    #[test]
+    fn put_empty_rects() {
+        let mut shadow = Shadow::new(4, 4, 1);
+        assert_eq!(shadow.put(&rect(1, 1, 0, 2), &[]), None);
+        assert_eq!(shadow.put(&rect(1, 1, 2, 0), &[]), None);
+    }
+
+    #[test]
+    fn put_multi_sample_pixels() {
+        let mut shadow = Shadow::new(3, 2, 4);
+        shadow.put(&rect(1, 1, 2, 1), &[1, 2, 3, 0, 4, 5, 6, 0]);
+        assert_eq!(&shadow.data[16..24], &[1, 2, 3, 0, 4, 5, 6, 0]);
+        assert!(shadow.data[..16].iter().all(|&b| b == 0xff), "untouched");
+    }
+}
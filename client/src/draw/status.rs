@@ -0,0 +1,76 @@
+use std::net::Ipv4Addr;
+
+use display::color::{BLACK, GRAY10, WHITE};
+use display::device::CURRENT_DEVICE;
+use display::framebuffer::{Framebuffer, UpdateMode};
+use display::geom::{BorderSpec, CornerSpec, Rectangle};
+use display::{pt, rect};
+
+use crate::draw::text::{draw_text_centered, text_size, GLYPH_HEIGHT};
+
+const DOTS: i32 = 3;
+
+/// Draws a small badge in the top right corner, signaling that the session is being re-established.
+/// The remaining screen keeps showing the last frame; the dots cycle with each `attempt`.
+pub fn draw_reconnecting(fb: &mut Box<dyn Framebuffer>, attempt: u32) -> Rectangle {
+    let unit = (CURRENT_DEVICE.dpi as i32 / 24).max(4);
+    let width = (2 * DOTS + 1) * unit;
+    let height = 3 * unit;
+    let margin = unit;
+
+    let right = fb.width() as i32 - margin;
+    let badge = rect![right - width, margin, right, margin + height];
+    fb.draw_rounded_rectangle_with_border(
+        &badge,
+        &CornerSpec::Uniform(unit),
+        &BorderSpec { thickness: (unit / 4).max(1) as u16, color: BLACK },
+        &WHITE,
+    );
+
+    let active = (attempt as i32 - 1).rem_euclid(DOTS);
+    for i in 0..DOTS {
+        let center = pt!(badge.min.x + (2 * i + 1) * unit + unit / 2, badge.min.y + height / 2);
+        let color = if i <= active { BLACK } else { GRAY10 };
+        fb.draw_disk(center, unit / 2, color);
+    }
+
+    fb.update(&badge, UpdateMode::FastMono).ok();
+    badge
+}
+
+/// Clears the screen for sleep, so the desktop doesn't stay visible while nobody is looking.
+pub fn draw_sleeping(fb: &mut Box<dyn Framebuffer>) -> Rectangle {
+    draw_message(fb, &["Sleeping", "", "Open the cover or press", "the power button to wake up"])
+}
+
+/// Clears the screen and lists the addresses a VNC server can reach us at in listen mode.
+pub fn draw_waiting(fb: &mut Box<dyn Framebuffer>, addresses: &[Ipv4Addr], port: u16) -> Rectangle {
+    let mut lines = vec!["Waiting for VNC server".to_string(), String::new()];
+    if addresses.is_empty() {
+        lines.push(format!("on port {}", port));
+    }
+    lines.extend(addresses.iter().map(|address| format!("{}:{}", address, port)));
+
+    draw_message(fb, &lines)
+}
+
+/// Clears the screen and centers `lines` on it, with a full refresh.
+/// The text is scaled to roughly 5mm glyphs, but shrinks until the longest line fits the screen.
+fn draw_message<S: AsRef<str>>(fb: &mut Box<dyn Framebuffer>, lines: &[S]) -> Rectangle {
+    let widest = lines.iter().map(|line| text_size(line.as_ref(), 1).0).max().unwrap_or(1).max(1);
+    let scale = (CURRENT_DEVICE.dpi as i32 / 36)
+        .min(fb.width() as i32 * 3 / 4 / widest)
+        .max(1);
+    let line_height = (GLYPH_HEIGHT + GLYPH_HEIGHT / 2) * scale;
+
+    fb.clear(WHITE);
+    let mut top = (fb.height() as i32 - lines.len() as i32 * line_height) / 2;
+    for line in lines {
+        draw_text_centered(fb, line.as_ref(), top, scale, BLACK);
+        top += line_height;
+    }
+
+    let screen = fb.rect();
+    fb.update(&screen, UpdateMode::Full).ok();
+    screen
+}
@@ -6,16 +6,20 @@ use vnc::Rect;
 
 use super::kobo::MapDelta;
 
+/// Bytes per pixel after `processing::streamline_pixel_color`: gray, or BGRx on color devices.
+pub fn samples() -> usize {
+    if CURRENT_DEVICE.color_samples() == 1 { 1 } else { 4 }
+}
+
 pub fn to_map<'a>(vnc_rect: &'a Rect, pixels: &'a Vec<u8>) -> ReadonlyPixmap<'a> {
     let w = vnc_rect.width as u32;
     let h = vnc_rect.height as u32;
     
-    let colors = if CURRENT_DEVICE.color_samples() == 1 { 1 } else { 4 };
     let pixmap = ReadonlyPixmap {
         width: w as u32,
         height: h as u32,
         data: pixels,
-        samples: colors,
+        samples: samples(),
     };
     return pixmap;
 }
@@ -28,6 +32,16 @@ pub fn to_delta_rect(vnc_rect: &Rect) -> Rectangle {
     rect![l, t, l + w, t + h]
 }
 
+/// The inverse of `to_delta_rect`, for rectangles within the desktop.
+pub fn to_vnc_rect(rect: &Rectangle) -> Rect {
+    Rect {
+        left: rect.min.x.max(0) as u16,
+        top: rect.min.y.max(0) as u16,
+        width: rect.width() as u16,
+        height: rect.height() as u16,
+    }
+}
+
 pub fn to_delta_map(dst: &Rect) -> MapDelta{
     MapDelta { 
         left: dst.left as u32, 
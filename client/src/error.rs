@@ -0,0 +1,57 @@
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while running an `einkvnc` session.
+#[derive(Debug)]
+pub enum Error {
+    /// The server can't be reached.
+    Connect(io::Error),
+    /// No usable authentication method, missing credentials, or the server rejected them.
+    Auth(String),
+    /// The VNC handshake or session failed.
+    Protocol(vnc::Error),
+    /// The framebuffer can't be opened or configured.
+    Framebuffer(anyhow::Error),
+    /// An input device can't be opened or read.
+    Input(io::Error),
+}
+
+impl Error {
+    /// Whether retrying the same operation later may succeed, e.g. after the network came back.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Error::Connect(_) | Error::Protocol(_))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Connect(error) => write!(f, "cannot connect: {}", error),
+            Error::Auth(reason) => write!(f, "authentication failed: {}", reason),
+            Error::Protocol(error) => write!(f, "VNC protocol error: {}", error),
+            Error::Framebuffer(error) => write!(f, "framebuffer error: {:#}", error),
+            Error::Input(error) => write!(f, "input device error: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Connect(error) | Error::Input(error) => Some(error),
+            Error::Protocol(error) => Some(error),
+            Error::Framebuffer(error) => Some(error.as_ref()),
+            Error::Auth(_) => None,
+        }
+    }
+}
+
+impl From<vnc::Error> for Error {
+    fn from(error: vnc::Error) -> Error {
+        match error {
+            vnc::Error::AuthenticationUnavailable => Error::Auth("no supported authentication method".to_string()),
+            vnc::Error::AuthenticationFailure(reason) => Error::Auth(reason),
+            error => Error::Protocol(error),
+        }
+    }
+}
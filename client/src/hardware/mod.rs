@@ -0,0 +1,42 @@
+#![allow(unused)]
+
+mod page_turn;
+mod rotation;
+
+pub use self::page_turn::{PageTurn, PageTurnConfig};
+pub use self::rotation::AutoRotate;
+
+use std::fs::File;
+use std::sync::mpsc::{Receiver, Sender};
+
+use display::framebuffer::{Display, Framebuffer};
+use display::input::{device_events, raw_events, ButtonCode, ButtonScheme, ButtonStatus, DeviceEvent, InputEvent};
+
+use crate::error::Error;
+
+/// Reads the buttons and sensors of the devices at `paths`, e.g. `/dev/input/event0` on Kobos. The
+/// returned sender takes `display::input::display_rotate_event`s, so buttons follow the rotation.
+pub fn record_device(paths: &[String], fb: &dyn Framebuffer) -> Result<(Sender<InputEvent>, Receiver<DeviceEvent>), Error> {
+    let (tx, rx) = record_raw(paths)?;
+    let display = Display { dims: fb.dims(), rotation: fb.rotation() };
+    Ok((tx, device_events(rx, display, ButtonScheme::Natural)))
+}
+
+/// Reads the raw events of the devices at `paths`, which are opened first to fail now, not
+/// silently in the reading thread.
+pub(crate) fn record_raw(paths: &[String]) -> Result<(Sender<InputEvent>, Receiver<InputEvent>), Error> {
+    for path in paths {
+        File::open(path).map_err(Error::Input)?;
+    }
+    Ok(raw_events(paths.to_vec()))
+}
+
+/// Whether `event` puts the device to sleep: the cover closing or the power button.
+pub fn is_sleep(event: &DeviceEvent) -> bool {
+    matches!(event, DeviceEvent::CoverOn | DeviceEvent::Button { code: ButtonCode::Power, status: ButtonStatus::Pressed, .. })
+}
+
+/// Whether `event` wakes the device up: the cover opening or the power button.
+pub fn is_wake(event: &DeviceEvent) -> bool {
+    matches!(event, DeviceEvent::CoverOff | DeviceEvent::Button { code: ButtonCode::Power, status: ButtonStatus::Pressed, .. })
+}
@@ -0,0 +1,104 @@
+use std::time::{Duration, Instant};
+
+use display::input::{ButtonCode, ButtonStatus, DeviceEvent};
+
+const XK_PRIOR: u32 = 0xff55;
+const XK_NEXT: u32 = 0xff56;
+
+/// How long the screen has to stay unchanged after a page turn before it's refreshed.
+const SETTLE: Duration = Duration::from_millis(600);
+
+/// The key chords of the page turn buttons, and whether turning a page cleans the screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageTurnConfig {
+    pub backward: Vec<u32>,
+    pub forward: Vec<u32>,
+    /// Redraw the whole screen with the full waveform once the page has been drawn.
+    pub refresh: bool,
+}
+
+impl Default for PageTurnConfig {
+    fn default() -> PageTurnConfig {
+        PageTurnConfig {
+            backward: vec![XK_PRIOR],
+            forward: vec![XK_NEXT],
+            refresh: false,
+        }
+    }
+}
+
+/// Turns the page turn buttons into key events, like an ebook reader turns pages.
+pub struct PageTurn {
+    config: PageTurnConfig,
+    /// When the last page was turned, while its refresh is due.
+    turned: Option<Instant>,
+}
+
+impl PageTurn {
+    pub fn new(config: &PageTurnConfig) -> PageTurn {
+        PageTurn {
+            config: config.clone(),
+            turned: None,
+        }
+    }
+
+    /// The key events (down, keysym) to send for `event`. Holding a button repeats its last key.
+    pub fn handle(&mut self, event: &DeviceEvent, now: Instant) -> Vec<(bool, u32)> {
+        let (code, status) = match *event {
+            DeviceEvent::Button { code, status, .. } => (code, status),
+            _ => return Vec::new(),
+        };
+        let keysyms = match code {
+            ButtonCode::Backward => &self.config.backward,
+            ButtonCode::Forward => &self.config.forward,
+            _ => return Vec::new(),
+        };
+        match status {
+            ButtonStatus::Pressed => keysyms.iter().map(|&keysym| (true, keysym)).collect(),
+            ButtonStatus::Repeated => keysyms.last().map(|&keysym| (true, keysym)).into_iter().collect(),
+            ButtonStatus::Released => {
+                if self.config.refresh {
+                    self.turned = Some(now);
+                }
+                keysyms.iter().rev().map(|&keysym| (false, keysym)).collect()
+            }
+        }
+    }
+
+    /// Whether the page turned has been drawn, as the screen hasn't changed since `last_draw`,
+    /// and is due its full refresh. Only answers true once per page.
+    pub fn settled(&mut self, last_draw: Instant, now: Instant) -> bool {
+        match self.turned {
+            Some(turned) if now.duration_since(turned.max(last_draw)) >= SETTLE => {
+                self.turned = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn button(code: ButtonCode, status: ButtonStatus) -> DeviceEvent {
+        DeviceEvent::Button { time: 0.0, code, status }
+    }
+
+    #[test]
+    fn buttons_press_keys_and_refresh_once_settled() {
+        let config = PageTurnConfig { backward: vec![0xffe3, 0xff50], refresh: true, ..PageTurnConfig::default() };
+        let mut page_turn = PageTurn::new(&config);
+        let start = Instant::now();
+        assert_eq!(page_turn.handle(&button(ButtonCode::Forward, ButtonStatus::Pressed), start), vec![(true, XK_NEXT)]);
+        assert_eq!(page_turn.handle(&button(ButtonCode::Backward, ButtonStatus::Repeated), start), vec![(true, 0xff50)]);
+        assert_eq!(page_turn.handle(&button(ButtonCode::Backward, ButtonStatus::Released), start), vec![(false, 0xff50), (false, 0xffe3)]);
+        assert!(page_turn.handle(&button(ButtonCode::Power, ButtonStatus::Pressed), start).is_empty());
+
+        let drawn = start + Duration::from_millis(300);
+        assert!(!page_turn.settled(drawn, drawn + Duration::from_millis(500)), "still drawing");
+        assert!(page_turn.settled(drawn, drawn + SETTLE));
+        assert!(!page_turn.settled(drawn, drawn + SETTLE * 2), "refreshed already");
+    }
+}
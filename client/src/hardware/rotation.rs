@@ -0,0 +1,63 @@
+use std::str::FromStr;
+
+use display::input::DeviceEvent;
+
+/// Whether the screen follows the G-sensor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AutoRotate {
+    Off,
+    On,
+    /// Auto rotation is held at the current orientation, until unlocked.
+    Locked,
+}
+
+impl AutoRotate {
+    pub const NAMES: [&'static str; 3] = ["off", "on", "locked"];
+
+    /// The rotation to switch the screen to for `event`, from the `current` one.
+    pub fn rotation(&self, event: &DeviceEvent, current: i8) -> Option<i8> {
+        match *event {
+            DeviceEvent::RotateScreen(rotation) if *self == AutoRotate::On && rotation != current => Some(rotation),
+            _ => None,
+        }
+    }
+
+    /// Holds or releases the orientation; has no effect while auto rotation is off.
+    pub fn toggle_lock(&mut self) {
+        *self = match self {
+            AutoRotate::Off => AutoRotate::Off,
+            AutoRotate::On => AutoRotate::Locked,
+            AutoRotate::Locked => AutoRotate::On,
+        };
+    }
+}
+
+impl FromStr for AutoRotate {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<AutoRotate, String> {
+        match name {
+            "off" => Ok(AutoRotate::Off),
+            "on" => Ok(AutoRotate::On),
+            "locked" => Ok(AutoRotate::Locked),
+            _ => Err(format!("unknown auto rotation '{}', expected one of {:?}", name, AutoRotate::NAMES)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_the_sensor_unless_locked() {
+        let mut auto_rotate = AutoRotate::On;
+        assert_eq!(auto_rotate.rotation(&DeviceEvent::RotateScreen(2), 1), Some(2));
+        assert_eq!(auto_rotate.rotation(&DeviceEvent::RotateScreen(1), 1), None, "already there");
+        assert_eq!(auto_rotate.rotation(&DeviceEvent::CoverOn, 1), None);
+        auto_rotate.toggle_lock();
+        assert_eq!(auto_rotate.rotation(&DeviceEvent::RotateScreen(2), 1), None);
+        auto_rotate.toggle_lock();
+        assert_eq!(auto_rotate, AutoRotate::On);
+    }
+}
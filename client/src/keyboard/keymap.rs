@@ -0,0 +1,292 @@
+use display::input::{InputEvent, EV_KEY, VAL_PRESS, VAL_RELEASE, VAL_REPEAT};
+
+use crate::keyboard::KeyboardLayout;
+
+const KEY_LEFTSHIFT: u16 = 42;
+const KEY_RIGHTSHIFT: u16 = 54;
+const KEY_CAPSLOCK: u16 = 58;
+const KEY_RIGHTALT: u16 = 100;
+
+const XK_SHIFT_L: u32 = 0xffe1;
+const XK_SHIFT_R: u32 = 0xffe2;
+const XK_CONTROL_L: u32 = 0xffe3;
+const XK_CONTROL_R: u32 = 0xffe4;
+const XK_CAPS_LOCK: u32 = 0xffe5;
+const XK_ALT_L: u32 = 0xffe9;
+const XK_ALT_R: u32 = 0xffea;
+const XK_SUPER_L: u32 = 0xffeb;
+const XK_SUPER_R: u32 = 0xffec;
+const XK_ISO_LEVEL3_SHIFT: u32 = 0xfe03;
+
+/// Keysyms of the keys that don't depend on the layout, by evdev code.
+const FUNCTION_KEYS: [(u16, u32); 55] = [
+    (1, 0xff1b),   // Escape
+    (14, 0xff08),  // BackSpace
+    (15, 0xff09),  // Tab
+    (28, 0xff0d),  // Return
+    (29, XK_CONTROL_L),
+    (KEY_LEFTSHIFT, XK_SHIFT_L),
+    (KEY_RIGHTSHIFT, XK_SHIFT_R),
+    (55, 0xffaa),  // KP_Multiply
+    (56, XK_ALT_L),
+    (57, 0x0020),  // space
+    (KEY_CAPSLOCK, XK_CAPS_LOCK),
+    (59, 0xffbe),  // F1
+    (60, 0xffbf),
+    (61, 0xffc0),
+    (62, 0xffc1),
+    (63, 0xffc2),
+    (64, 0xffc3),
+    (65, 0xffc4),
+    (66, 0xffc5),
+    (67, 0xffc6),
+    (68, 0xffc7),  // F10
+    (69, 0xff7f),  // Num_Lock
+    (70, 0xff14),  // Scroll_Lock
+    (71, 0xffb7),  // KP_7
+    (72, 0xffb8),
+    (73, 0xffb9),
+    (74, 0xffad),  // KP_Subtract
+    (75, 0xffb4),  // KP_4
+    (76, 0xffb5),
+    (77, 0xffb6),
+    (78, 0xffab),  // KP_Add
+    (79, 0xffb1),  // KP_1
+    (80, 0xffb2),
+    (81, 0xffb3),
+    (82, 0xffb0),  // KP_0
+    (83, 0xffae),  // KP_Decimal
+    (87, 0xffc8),  // F11
+    (88, 0xffc9),  // F12
+    (96, 0xff8d),  // KP_Enter
+    (97, XK_CONTROL_R),
+    (98, 0xffaf),  // KP_Divide
+    (99, 0xff61),  // Print
+    (102, 0xff50), // Home
+    (103, 0xff52), // Up
+    (104, 0xff55), // Prior
+    (105, 0xff51), // Left
+    (106, 0xff53), // Right
+    (107, 0xff57), // End
+    (108, 0xff54), // Down
+    (109, 0xff56), // Next
+    (110, 0xff63), // Insert
+    (111, 0xffff), // Delete
+    (119, 0xff13), // Pause
+    (125, XK_SUPER_L),
+    (126, XK_SUPER_R),
+];
+
+/// Turns the EV_KEY events of keyboards into VNC key events, tracking the modifiers
+/// that select what the layout types.
+pub struct Keyboard {
+    layout: KeyboardLayout,
+    shift: [bool; 2],
+    altgr: bool,
+    caps_lock: bool,
+    /// The keysym each held key was pressed with, so releasing it matches even after the modifiers changed.
+    pressed: Vec<(u16, u32)>,
+}
+
+impl Keyboard {
+    pub fn new(layout: KeyboardLayout) -> Keyboard {
+        Keyboard {
+            layout,
+            shift: [false; 2],
+            altgr: false,
+            caps_lock: false,
+            pressed: Vec::new(),
+        }
+    }
+
+    /// The key event (down, keysym) to send for `event`, if any. Auto repeat sends further
+    /// downs, except for modifiers.
+    pub fn handle(&mut self, event: &InputEvent) -> Option<(bool, u32)> {
+        if event.kind != EV_KEY {
+            return None;
+        }
+        let code = event.code;
+        match event.value {
+            VAL_PRESS => {
+                match code {
+                    KEY_LEFTSHIFT => self.shift[0] = true,
+                    KEY_RIGHTSHIFT => self.shift[1] = true,
+                    KEY_RIGHTALT => self.altgr = self.layout.has_altgr(),
+                    KEY_CAPSLOCK => self.caps_lock = !self.caps_lock,
+                    _ => (),
+                }
+                let keysym = self.keysym(code)?;
+                self.pressed.retain(|&(held, _)| held != code);
+                self.pressed.push((code, keysym));
+                Some((true, keysym))
+            }
+            VAL_REPEAT => {
+                let &(_, keysym) = self.pressed.iter().find(|&&(held, _)| held == code)?;
+                if is_modifier(keysym) {
+                    return None;
+                }
+                Some((true, keysym))
+            }
+            VAL_RELEASE => {
+                match code {
+                    KEY_LEFTSHIFT => self.shift[0] = false,
+                    KEY_RIGHTSHIFT => self.shift[1] = false,
+                    KEY_RIGHTALT => self.altgr = false,
+                    _ => (),
+                }
+                let index = self.pressed.iter().position(|&(held, _)| held == code)?;
+                let (_, keysym) = self.pressed.remove(index);
+                Some((false, keysym))
+            }
+            _ => None,
+        }
+    }
+
+    /// Releases all held keys, e.g. before the session is replaced.
+    pub fn release_all(&mut self) -> Vec<(bool, u32)> {
+        self.shift = [false; 2];
+        self.altgr = false;
+        self.pressed.drain(..).map(|(_, keysym)| (false, keysym)).collect()
+    }
+
+    fn keysym(&self, code: u16) -> Option<u32> {
+        if let Some(&(_, keysym)) = FUNCTION_KEYS.iter().find(|&&(key, _)| key == code) {
+            return Some(keysym);
+        }
+        if code == KEY_RIGHTALT {
+            return Some(if self.layout.has_altgr() { XK_ISO_LEVEL3_SHIFT } else { XK_ALT_R });
+        }
+        let shift = self.shift[0] || self.shift[1];
+        let letter = self.layout.character(code, false, false).is_some_and(char::is_alphabetic);
+        let character = self.layout.character(code, shift ^ (self.caps_lock && letter), self.altgr)?;
+        Some(keysym(character))
+    }
+}
+
+/// The keysym of a character: Latin-1 maps directly, everything else to the Unicode range.
+pub(crate) fn keysym(character: char) -> u32 {
+    match character as u32 {
+        code @ (0x20..=0x7e | 0xa0..=0xff) => code,
+        code => 0x0100_0000 + code,
+    }
+}
+
+/// The keysyms of a key chord like `ctrl+z` or `shift+0xff09`, in the order they are pressed.
+pub(crate) fn chord(keys: &str) -> Option<Vec<u32>> {
+    keys.split('+').map(named_keysym).collect()
+}
+
+fn named_keysym(name: &str) -> Option<u32> {
+    let keysym = match name.to_lowercase().as_str() {
+        "ctrl" | "control" => XK_CONTROL_L,
+        "shift" => XK_SHIFT_L,
+        "alt" => XK_ALT_L,
+        "altgr" => XK_ISO_LEVEL3_SHIFT,
+        "super" | "meta" => XK_SUPER_L,
+        "esc" | "escape" => 0xff1b,
+        "tab" => 0xff09,
+        "enter" | "return" => 0xff0d,
+        "backspace" => 0xff08,
+        "delete" => 0xffff,
+        "space" => 0x0020,
+        "home" => 0xff50,
+        "left" => 0xff51,
+        "up" => 0xff52,
+        "right" => 0xff53,
+        "down" => 0xff54,
+        "pageup" => 0xff55,
+        "pagedown" => 0xff56,
+        "end" => 0xff57,
+        hex if hex.starts_with("0x") => u32::from_str_radix(&hex[2..], 16).ok()?,
+        _ => {
+            let mut chars = name.chars();
+            let character = chars.next()?;
+            if chars.next().is_some() {
+                return None;
+            }
+            keysym(character)
+        }
+    };
+    Some(keysym)
+}
+
+fn is_modifier(keysym: u32) -> bool {
+    matches!(keysym, XK_SHIFT_L..=XK_SUPER_R | XK_ISO_LEVEL3_SHIFT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: u16, value: i32) -> InputEvent {
+        InputEvent {
+            time: libc::timeval { tv_sec: 0, tv_usec: 0 },
+            kind: EV_KEY,
+            code,
+            value,
+        }
+    }
+
+    fn type_keys(keyboard: &mut Keyboard, events: &[(u16, i32)]) -> Vec<(bool, u32)> {
+        events.iter().filter_map(|&(code, value)| keyboard.handle(&key(code, value))).collect()
+    }
+
+    #[test]
+    fn modifiers_select_keysyms() {
+        let mut keyboard = Keyboard::new(KeyboardLayout::Us);
+        assert_eq!(type_keys(&mut keyboard, &[(30, 1), (30, 0)]), vec![(true, 'a' as u32), (false, 'a' as u32)]);
+        assert_eq!(type_keys(&mut keyboard, &[(42, 1), (30, 1), (2, 1), (42, 0), (30, 0), (2, 0)]), vec![
+            (true, XK_SHIFT_L),
+            (true, 'A' as u32),
+            (true, '!' as u32),
+            (false, XK_SHIFT_L),
+            (false, 'A' as u32),
+            (false, '!' as u32),
+        ], "releases match the presses");
+        assert_eq!(type_keys(&mut keyboard, &[(58, 1), (58, 0), (30, 1), (2, 1)]), vec![
+            (true, XK_CAPS_LOCK),
+            (false, XK_CAPS_LOCK),
+            (true, 'A' as u32),
+            (true, '1' as u32),
+        ], "caps lock only shifts letters");
+    }
+
+    #[test]
+    fn layouts_and_altgr() {
+        let mut keyboard = Keyboard::new(KeyboardLayout::De);
+        assert_eq!(type_keys(&mut keyboard, &[(21, 1), (39, 1)]), vec![(true, 'z' as u32), (true, 0xf6)]);
+        assert_eq!(type_keys(&mut keyboard, &[(100, 1), (16, 1), (18, 1)]), vec![
+            (true, XK_ISO_LEVEL3_SHIFT),
+            (true, '@' as u32),
+            (true, 0x0100_20ac), // €
+        ]);
+
+        let mut keyboard = Keyboard::new(KeyboardLayout::Us);
+        assert_eq!(type_keys(&mut keyboard, &[(100, 1), (16, 1), (100, 0)]), vec![
+            (true, XK_ALT_R),
+            (true, 'q' as u32),
+            (false, XK_ALT_R),
+        ], "just Alt without a third level");
+    }
+
+    #[test]
+    fn repeats_all_but_modifiers() {
+        let mut keyboard = Keyboard::new(KeyboardLayout::Us);
+        assert_eq!(type_keys(&mut keyboard, &[(29, 1), (29, 2), (14, 1), (14, 2), (14, 2)]), vec![
+            (true, XK_CONTROL_L),
+            (true, 0xff08),
+            (true, 0xff08),
+            (true, 0xff08),
+        ]);
+        assert_eq!(keyboard.release_all(), vec![(false, XK_CONTROL_L), (false, 0xff08)]);
+    }
+
+    #[test]
+    fn parses_chords() {
+        assert_eq!(chord("ctrl+z"), Some(vec![XK_CONTROL_L, 'z' as u32]));
+        assert_eq!(chord("Shift+Tab"), Some(vec![XK_SHIFT_L, 0xff09]));
+        assert_eq!(chord("0xffbe"), Some(vec![0xffbe]));
+        assert_eq!(chord("ctrl+"), None);
+        assert_eq!(chord("hyper+z"), None);
+    }
+}
@@ -0,0 +1,84 @@
+use std::str::FromStr;
+
+/// The characters of one row of keys with consecutive evdev codes, unshifted, shifted and with
+/// AltGr. A space in `altgr` means the key has no third level.
+struct Row {
+    first_code: u16,
+    normal: &'static str,
+    shifted: &'static str,
+    altgr: &'static str,
+}
+
+const US: [Row; 5] = [
+    Row { first_code: 2, normal: "1234567890-=", shifted: "!@#$%^&*()_+", altgr: "" },
+    Row { first_code: 16, normal: "qwertyuiop[]", shifted: "QWERTYUIOP{}", altgr: "" },
+    Row { first_code: 30, normal: "asdfghjkl;'`", shifted: "ASDFGHJKL:\"~", altgr: "" },
+    Row { first_code: 43, normal: "\\zxcvbnm,./", shifted: "|ZXCVBNM<>?", altgr: "" },
+    Row { first_code: 86, normal: "<", shifted: ">", altgr: "" },
+];
+
+const GB: [Row; 5] = [
+    Row { first_code: 2, normal: "1234567890-=", shifted: "!\"£$%^&*()_+", altgr: "" },
+    Row { first_code: 16, normal: "qwertyuiop[]", shifted: "QWERTYUIOP{}", altgr: "  €" },
+    Row { first_code: 30, normal: "asdfghjkl;'`", shifted: "ASDFGHJKL:@¬", altgr: "" },
+    Row { first_code: 43, normal: "#zxcvbnm,./", shifted: "~ZXCVBNM<>?", altgr: "" },
+    Row { first_code: 86, normal: "\\", shifted: "|", altgr: "" },
+];
+
+const DE: [Row; 5] = [
+    Row { first_code: 2, normal: "1234567890ß´", shifted: "!\"§$%&/()=?`", altgr: " ²³   {[]}\\ " },
+    Row { first_code: 16, normal: "qwertzuiopü+", shifted: "QWERTZUIOPÜ*", altgr: "@ €        ~" },
+    Row { first_code: 30, normal: "asdfghjklöä^", shifted: "ASDFGHJKLÖÄ°", altgr: "" },
+    Row { first_code: 43, normal: "#yxcvbnm,.-", shifted: "'YXCVBNM;:_", altgr: "       µ" },
+    Row { first_code: 86, normal: "<", shifted: ">", altgr: "|" },
+];
+
+/// The layout of the keys that type characters; the other keys are the same everywhere.
+/// Dead keys type their accent, they don't combine with the next key.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KeyboardLayout {
+    Us,
+    Gb,
+    De,
+}
+
+impl KeyboardLayout {
+    pub const NAMES: [&'static str; 3] = ["us", "gb", "de"];
+
+    /// The character typed by the key with evdev `code`, if it types one.
+    pub fn character(&self, code: u16, shifted: bool, altgr: bool) -> Option<char> {
+        let row = self.rows().iter().find(|row| code >= row.first_code && ((code - row.first_code) as usize) < row.normal.chars().count())?;
+        let index = (code - row.first_code) as usize;
+        if altgr {
+            return row.altgr.chars().nth(index).filter(|&c| c != ' ');
+        }
+        let level = if shifted { row.shifted } else { row.normal };
+        level.chars().nth(index)
+    }
+
+    /// Whether AltGr types a third level; otherwise the right Alt key is just Alt.
+    pub fn has_altgr(&self) -> bool {
+        self.rows().iter().any(|row| !row.altgr.is_empty())
+    }
+
+    fn rows(&self) -> &'static [Row] {
+        match self {
+            KeyboardLayout::Us => &US,
+            KeyboardLayout::Gb => &GB,
+            KeyboardLayout::De => &DE,
+        }
+    }
+}
+
+impl FromStr for KeyboardLayout {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<KeyboardLayout, String> {
+        match name {
+            "us" => Ok(KeyboardLayout::Us),
+            "gb" => Ok(KeyboardLayout::Gb),
+            "de" => Ok(KeyboardLayout::De),
+            _ => Err(format!("unknown keyboard layout '{}', expected one of {:?}", name, KeyboardLayout::NAMES)),
+        }
+    }
+}
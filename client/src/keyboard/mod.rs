@@ -0,0 +1,23 @@
+#![allow(unused)]
+
+mod keymap;
+mod layout;
+mod onscreen;
+
+pub use self::keymap::Keyboard;
+pub(crate) use self::keymap::chord;
+pub use self::layout::KeyboardLayout;
+pub use self::onscreen::OnScreenKeyboard;
+
+use std::sync::mpsc::Receiver;
+
+use display::input::InputEvent;
+
+use crate::error::Error;
+use crate::hardware::record_raw;
+
+/// Reads the raw events of the keyboard devices at `paths`, e.g. `/dev/input/event3` for a USB OTG keyboard.
+pub fn record_keyboards(paths: &[String]) -> Result<Receiver<InputEvent>, Error> {
+    let (_, rx) = record_raw(paths)?;
+    Ok(rx)
+}
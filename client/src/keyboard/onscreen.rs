@@ -0,0 +1,387 @@
+use display::color::{Color, BLACK, KEYBOARD_BG, WHITE};
+use display::device::CURRENT_DEVICE;
+use display::framebuffer::{Framebuffer, UpdateMode};
+use display::geom::{BorderSpec, CornerSpec, Point, Rectangle};
+use display::{pt, rect};
+
+use crate::draw::shadow::Shadow;
+use crate::draw::text::{draw_text, text_size, GLYPH_HEIGHT};
+use crate::draw::util;
+use crate::keyboard::keymap::keysym;
+use crate::touch::Touch;
+
+/// Keys per row, in quarters of a standard key.
+const ROW_WIDTH: i32 = 60;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Modifier {
+    Shift,
+    Ctrl,
+    Alt,
+    Super,
+}
+
+impl Modifier {
+    fn keysym(self) -> u32 {
+        match self {
+            Modifier::Shift => 0xffe1,
+            Modifier::Ctrl => 0xffe3,
+            Modifier::Alt => 0xffe9,
+            Modifier::Super => 0xffeb,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Action {
+    /// Types the first character, the second with Shift.
+    Char(char, char),
+    Keysym(u32),
+    Modifier(Modifier),
+    Hide,
+}
+
+struct Key {
+    label: &'static str,
+    action: Action,
+    /// In quarters of a standard key.
+    width: i32,
+}
+
+const fn chr(normal: char, shifted: char) -> Key {
+    Key { label: "", action: Action::Char(normal, shifted), width: 4 }
+}
+
+const fn sym(label: &'static str, keysym: u32, width: i32) -> Key {
+    Key { label, action: Action::Keysym(keysym), width }
+}
+
+const fn modifier(label: &'static str, modifier: Modifier, width: i32) -> Key {
+    Key { label, action: Action::Modifier(modifier), width }
+}
+
+const ROWS: [&[Key]; 5] = [
+    &[
+        sym("Esc", 0xff1b, 4),
+        chr('1', '!'), chr('2', '@'), chr('3', '#'), chr('4', '$'), chr('5', '%'),
+        chr('6', '^'), chr('7', '&'), chr('8', '*'), chr('9', '('), chr('0', ')'),
+        chr('-', '_'), chr('=', '+'),
+        sym("Bksp", 0xff08, 8),
+    ],
+    &[
+        sym("Tab", 0xff09, 6),
+        chr('q', 'Q'), chr('w', 'W'), chr('e', 'E'), chr('r', 'R'), chr('t', 'T'),
+        chr('y', 'Y'), chr('u', 'U'), chr('i', 'I'), chr('o', 'O'), chr('p', 'P'),
+        chr('[', '{'), chr(']', '}'),
+        Key { label: "", action: Action::Char('\\', '|'), width: 6 },
+    ],
+    &[
+        modifier("Ctrl", Modifier::Ctrl, 7),
+        chr('a', 'A'), chr('s', 'S'), chr('d', 'D'), chr('f', 'F'), chr('g', 'G'),
+        chr('h', 'H'), chr('j', 'J'), chr('k', 'K'), chr('l', 'L'),
+        chr(';', ':'), chr('\'', '"'),
+        sym("Enter", 0xff0d, 9),
+    ],
+    &[
+        modifier("Shift", Modifier::Shift, 9),
+        chr('z', 'Z'), chr('x', 'X'), chr('c', 'C'), chr('v', 'V'), chr('b', 'B'),
+        chr('n', 'N'), chr('m', 'M'), chr(',', '<'), chr('.', '>'), chr('/', '?'),
+        sym("^", 0xff52, 4),
+        sym("Del", 0xffff, 7),
+    ],
+    &[
+        modifier("Super", Modifier::Super, 5),
+        modifier("Alt", Modifier::Alt, 5),
+        chr('`', '~'),
+        Key { label: "", action: Action::Char(' ', ' '), width: 28 },
+        sym("<", 0xff51, 4),
+        sym("v", 0xff54, 4),
+        sym(">", 0xff53, 4),
+        Key { label: "Hide", action: Action::Hide, width: 6 },
+    ],
+];
+
+enum Tracking {
+    Idle,
+    /// A finger went down on the key with this index.
+    Key(Option<usize>),
+    /// A finger went down on the bottom edge; held back until it's either a swipe or a tap.
+    Swipe { start: Point, held: Vec<Touch> },
+    /// The touch was consumed; ignore it until the finger is lifted.
+    Ignore,
+    Pointer,
+}
+
+/// A keyboard drawn over the bottom of the remote screen, shown by swiping up from the
+/// bottom edge. Taps become key events; modifiers latch until the next key.
+pub struct OnScreenKeyboard {
+    visible: bool,
+    area: Rectangle,
+    keys: Vec<(Rectangle, &'static Key)>,
+    latched: Vec<Modifier>,
+    tracking: Tracking,
+}
+
+impl OnScreenKeyboard {
+    pub fn new() -> OnScreenKeyboard {
+        let keys = ROWS.iter().flat_map(|row| row.iter()).map(|key| (rect![0, 0, 0, 0], key)).collect();
+        OnScreenKeyboard {
+            visible: false,
+            area: rect![0, 0, 0, 0],
+            keys,
+            latched: Vec::new(),
+            tracking: Tracking::Idle,
+        }
+    }
+
+    /// Routes a touch either to the keyboard, returning the key events to send, or to the
+    /// server, returning the touches to send as pointer events.
+    pub fn touch(&mut self, fb: &mut Box<dyn Framebuffer>, shadow: &Shadow, touch: Touch) -> (Vec<Touch>, Vec<(bool, u32)>) {
+        let position = pt!(touch.position.x, touch.position.y);
+        let released = touch.button == Some(0);
+        let tracking = std::mem::replace(&mut self.tracking, Tracking::Idle);
+        match tracking {
+            Tracking::Idle if touch.button == Some(1) => {
+                if self.visible && self.area.includes(position) {
+                    let index = self.keys.iter().position(|(rect, _)| rect.includes(position));
+                    if let Some(index) = index {
+                        self.draw_key(fb, index, true);
+                        fb.update(&self.keys[index].0, UpdateMode::FastMono).ok();
+                    }
+                    self.tracking = Tracking::Key(index);
+                } else if !self.visible && position.y >= fb.height() as i32 - edge() {
+                    self.tracking = Tracking::Swipe { start: position, held: vec![touch] };
+                } else {
+                    self.tracking = Tracking::Pointer;
+                    return (vec![touch], Vec::new());
+                }
+                (Vec::new(), Vec::new())
+            }
+            Tracking::Idle => (vec![touch], Vec::new()),
+            Tracking::Key(index) if released => {
+                let events = index.map(|index| self.tap(fb, index, shadow)).unwrap_or_default();
+                (Vec::new(), events)
+            }
+            Tracking::Key(index) => {
+                self.tracking = Tracking::Key(index);
+                (Vec::new(), Vec::new())
+            }
+            Tracking::Swipe { start, mut held } => {
+                if start.y - position.y >= 3 * edge() {
+                    self.show(fb);
+                    if !released {
+                        self.tracking = Tracking::Ignore;
+                    }
+                    return (Vec::new(), Vec::new());
+                }
+                held.push(touch);
+                if released {
+                    return (held, Vec::new());
+                }
+                self.tracking = Tracking::Swipe { start, held };
+                (Vec::new(), Vec::new())
+            }
+            Tracking::Ignore => {
+                if !released {
+                    self.tracking = Tracking::Ignore;
+                }
+                (Vec::new(), Vec::new())
+            }
+            Tracking::Pointer => {
+                if !released {
+                    self.tracking = Tracking::Pointer;
+                }
+                (vec![touch], Vec::new())
+            }
+        }
+    }
+
+    /// Draws the keyboard again, if `dirty` painted the remote screen over it.
+    pub fn redraw(&mut self, fb: &mut Box<dyn Framebuffer>, dirty: &[Rectangle]) {
+        if self.visible && dirty.iter().any(|rect| rect.overlaps(&self.area)) {
+            self.draw(fb);
+        }
+    }
+
+    /// The screen area the keyboard covers while it's visible.
+    pub fn overlay(&self) -> Option<Rectangle> {
+        self.visible.then_some(self.area)
+    }
+
+    pub fn show(&mut self, fb: &mut Box<dyn Framebuffer>) {
+        self.visible = true;
+        self.layout(fb.width() as i32, fb.height() as i32);
+        self.draw(fb);
+        fb.update(&self.area, UpdateMode::Gui).ok();
+    }
+
+    /// Lays the keyboard out again for the rotated screen; it's drawn with the next frame.
+    pub fn rotate(&mut self, fb: &dyn Framebuffer) {
+        if self.visible {
+            self.layout(fb.width() as i32, fb.height() as i32);
+        }
+    }
+
+    /// Hides the keyboard and restores the remote screen below it.
+    pub fn hide(&mut self, fb: &mut Box<dyn Framebuffer>, shadow: &Shadow) {
+        if !self.visible {
+            return;
+        }
+        self.visible = false;
+        fb.draw_rectangle(&self.area, WHITE);
+        let desktop = rect![0, 0, shadow.width as i32, shadow.height as i32];
+        if let Some(below) = self.area.intersection(&desktop) {
+            shadow.draw(fb, &util::to_vnc_rect(&below));
+        }
+        fb.update(&self.area, UpdateMode::Partial).ok();
+    }
+
+    fn layout(&mut self, width: i32, height: i32) {
+        let quarter = width / ROW_WIDTH;
+        let key_height = (4 * quarter).min(height / 2 / ROWS.len() as i32);
+        let left = (width - ROW_WIDTH * quarter) / 2;
+        let top = height - ROWS.len() as i32 * key_height - quarter;
+        self.area = rect![0, top - quarter, width, height];
+
+        self.keys.clear();
+        for (row, keys) in ROWS.iter().enumerate() {
+            let mut x = left;
+            let y = top + row as i32 * key_height;
+            for key in keys.iter() {
+                let next = x + key.width * quarter;
+                self.keys.push((rect![x, y, next, y + key_height], key));
+                x = next;
+            }
+        }
+    }
+
+    fn draw(&mut self, fb: &mut Box<dyn Framebuffer>) {
+        fb.draw_rectangle(&self.area, KEYBOARD_BG);
+        for index in 0..self.keys.len() {
+            self.draw_key(fb, index, false);
+        }
+    }
+
+    /// Draws a key, inverted while `pressed` or latched.
+    fn draw_key(&self, fb: &mut Box<dyn Framebuffer>, index: usize, pressed: bool) {
+        let (rect, key) = &self.keys[index];
+        let inverted = pressed || matches!(key.action, Action::Modifier(modifier) if self.latched.contains(&modifier));
+        let (background, foreground) = if inverted { (BLACK, WHITE) } else { (WHITE, BLACK) };
+
+        let gap = (rect.height() as i32 / 16).max(1);
+        let face = rect![rect.min.x + gap, rect.min.y + gap, rect.max.x - gap, rect.max.y - gap];
+        fb.draw_rounded_rectangle_with_border(
+            &face,
+            &CornerSpec::Uniform(2 * gap),
+            &BorderSpec { thickness: (gap / 2).max(1) as u16, color: BLACK },
+            &background,
+        );
+
+        let label = self.label(key);
+        let scale = (face.height() as i32 / 3 / GLYPH_HEIGHT).max(1);
+        let (width, height) = text_size(&label, scale);
+        let origin = pt!(face.min.x + (face.width() as i32 - width) / 2, face.min.y + (face.height() as i32 - height) / 2);
+        draw_text(fb, &label, origin, scale, foreground);
+    }
+
+    fn label(&self, key: &Key) -> String {
+        match key.action {
+            Action::Char(_, shifted) if self.latched.contains(&Modifier::Shift) => shifted.to_string(),
+            Action::Char(normal, _) => normal.to_string(),
+            _ => key.label.to_string(),
+        }
+    }
+
+    /// Handles a lifted finger on the key `index` and redraws what changed.
+    fn tap(&mut self, fb: &mut Box<dyn Framebuffer>, index: usize, shadow: &Shadow) -> Vec<(bool, u32)> {
+        let shifted = self.latched.contains(&Modifier::Shift);
+        let events = self.press(index);
+        if let Action::Hide = self.keys[index].1.action {
+            self.hide(fb, shadow);
+        } else if shifted != self.latched.contains(&Modifier::Shift) {
+            self.draw(fb); // the labels change
+            fb.update(&self.area, UpdateMode::Gui).ok();
+        } else {
+            let modifiers: Vec<usize> = (0..self.keys.len())
+                .filter(|&other| other == index || matches!(self.keys[other].1.action, Action::Modifier(_)))
+                .collect();
+            for other in modifiers {
+                self.draw_key(fb, other, false);
+                fb.update(&self.keys[other].0, UpdateMode::FastMono).ok();
+            }
+        }
+        events
+    }
+
+    /// The key events for a tap on the key `index`. Modifiers only toggle their latch, other
+    /// keys are sent with the latched modifiers held, and release them.
+    fn press(&mut self, index: usize) -> Vec<(bool, u32)> {
+        let keysym = match self.keys[index].1.action {
+            Action::Modifier(modifier) => {
+                match self.latched.iter().position(|&latched| latched == modifier) {
+                    Some(position) => {
+                        self.latched.remove(position);
+                    }
+                    None => self.latched.push(modifier),
+                }
+                return Vec::new();
+            }
+            Action::Hide => return Vec::new(),
+            Action::Char(_, shifted) if self.latched.contains(&Modifier::Shift) => keysym(shifted),
+            Action::Char(normal, _) => keysym(normal),
+            Action::Keysym(keysym) => keysym,
+        };
+        let mut events: Vec<(bool, u32)> = self.latched.iter().map(|modifier| (true, modifier.keysym())).collect();
+        events.push((true, keysym));
+        events.push((false, keysym));
+        events.extend(self.latched.drain(..).rev().map(|modifier| (false, modifier.keysym())));
+        events
+    }
+}
+
+impl Default for OnScreenKeyboard {
+    fn default() -> OnScreenKeyboard {
+        OnScreenKeyboard::new()
+    }
+}
+
+/// Height of the bottom edge that starts the swipe showing the keyboard, about 4mm.
+fn edge() -> i32 {
+    (CURRENT_DEVICE.dpi as i32 / 6).max(8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(keyboard: &OnScreenKeyboard, label: &str) -> usize {
+        keyboard.keys.iter().position(|(_, key)| keyboard.label(key) == label).unwrap()
+    }
+
+    #[test]
+    fn rows_are_equally_wide() {
+        for row in ROWS.iter() {
+            assert_eq!(row.iter().map(|key| key.width).sum::<i32>(), ROW_WIDTH);
+        }
+    }
+
+    #[test]
+    fn modifiers_latch_until_the_next_key() {
+        let mut keyboard = OnScreenKeyboard::new();
+        let ctrl = index(&keyboard, "Ctrl");
+        let shift = index(&keyboard, "Shift");
+        assert!(keyboard.press(ctrl).is_empty());
+        assert!(keyboard.press(shift).is_empty());
+        let c = index(&keyboard, "C");
+        assert_eq!(keyboard.press(c), vec![
+            (true, 0xffe3),
+            (true, 0xffe1),
+            (true, 'C' as u32),
+            (false, 'C' as u32),
+            (false, 0xffe1),
+            (false, 0xffe3),
+        ]);
+        let esc = index(&keyboard, "Esc");
+        assert_eq!(keyboard.press(esc), vec![(true, 0xff1b), (false, 0xff1b)], "latches were released");
+    }
+}
@@ -7,129 +7,436 @@ extern crate flate2;
 
 pub mod config;
 mod draw;
+mod error;
+pub mod hardware;
+pub mod keyboard;
+mod menu;
 pub mod processing;
-mod touch;
+pub mod touch;
 pub mod vnc;
 
 extern crate vnc as vnc_client;
-use vnc_client::{client, Client, Rect};
+use vnc_client::{client, Rect};
 
 use crate::config::Config;
 use crate::draw::Draw;
-use crate::processing::PostProcBin;
-use crate::touch::{mouse_btn_to_vnc, Touch, TouchEventListener, MOUSE_UNKNOWN};
+use crate::draw::cursor::{Cursor, CursorShape};
+use crate::draw::shadow::Shadow;
+use crate::hardware::{AutoRotate, PageTurn};
+use crate::keyboard::{Keyboard, OnScreenKeyboard};
+use crate::menu::{InputMode, Menu, MenuAction};
+use crate::processing::{PixelLut, PostProcBin};
+use crate::touch::{Gestures, Stylus, Touch, TouchEventListener, TouchTransform};
+use crate::vnc::{Backoff, ContinuousUpdates, Extension, Session};
+use display::color::WHITE;
 use display::device::CURRENT_DEVICE;
-use display::framebuffer::{Framebuffer, Pixmap};
-use display::rect;
+use display::framebuffer::Framebuffer;
+use display::geom::Rectangle;
+use display::input::{display_rotate_event, ButtonCode, ButtonStatus, DeviceEvent};
+use display::{pt, rect};
 
-use anyhow::Error;
-use log::{debug, error, info};
+pub use crate::draw::kobo::new_frame_buffer;
+pub use crate::draw::{Content, RefreshPolicy, RefreshPreset, TileWear};
+pub use crate::error::Error;
+
+use log::{debug, error, info, warn};
 use std::sync::mpsc;
 use std::sync::mpsc::Receiver;
 use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 
-pub fn run(vnc: &mut Client, fb: &mut Box<dyn Framebuffer>, config: &Config) -> Result<(), Error> {
+/// Opens the VNC session. With `config.reconnect` failed attempts are retried with an
+/// exponential backoff, while a reconnecting indicator is shown on top of the last frame.
+/// With `config.listen` a waiting screen is shown until the server connects to us instead.
+/// With `resize` the server is asked to match its desktop to the screen, once it announced the extension.
+pub fn connect(config: &Config, fb: &mut Box<dyn Framebuffer>) -> Result<Session, Error> {
+    let mut vnc = if let Some(port) = config.listen {
+        vnc::listen(config.connection, port, |addresses, port| {
+            draw::status::draw_waiting(fb, addresses, port);
+        })?
+    } else if !config.reconnect {
+        vnc::connect(config.connection)?
+    } else {
+        let mut backoff = Backoff::default();
+        vnc::connect_with_backoff(config.connection, &mut backoff, |_, attempt| {
+            draw::status::draw_reconnecting(fb, attempt);
+        })?
+    };
+    if config.connection.resize {
+        fit_desktop(&mut vnc, fb.as_ref())?;
+    }
+    Ok(vnc)
+}
+
+/// Asks the server for a desktop of the (rotated) screen size, unless it already has it.
+pub fn fit_desktop(vnc: &mut Session, fb: &dyn Framebuffer) -> Result<(), Error> {
+    let size = (fb.width() as u16, fb.height() as u16);
+    if vnc.size() != size {
+        info!("requesting a {}x{} desktop instead of {}x{}", size.0, size.1, vnc.size().0, vnc.size().1);
+        vnc.set_desktop_size(size.0, size.1)?;
+    }
+    Ok(())
+}
+
+pub fn run(vnc: &mut Session, fb: &mut Box<dyn Framebuffer>, config: &Config) -> Result<(), Error> {
     #[cfg(feature = "eink_device")]
     debug!(
         "running on device model=\"{}\" /dpi={} /dims={}x{}",
         CURRENT_DEVICE.model, CURRENT_DEVICE.dpi, CURRENT_DEVICE.dims.0, CURRENT_DEVICE.dims.1
     );
 
-    let (width, height) = vnc.size();
-    vnc.format();
+    let (mut width, mut height) = vnc.size();
 
     const FRAME_MS: u64 = 1000 / 30;
 
-    let mut draw: Draw = Draw::new();
-    let post_proc_bin = PostProcBin::new(&config.processing);
+    let mut draw: Draw = Draw::new(config.refresh_policy.as_ref());
+    let mut shadow = Shadow::new(width, height, draw::util::samples());
+    let mut post_proc_bin = PostProcBin::new(&config.processing);
+    let mut format = vnc.format();
+    let mut lut = PixelLut::for_format(&format, &post_proc_bin);
+    let mut cursor = Cursor::new();
+    let mut updates = ContinuousUpdates::new();
+
+    // recorded in view-only mode as well, for the menu, which can switch the input back on;
+    // `InputMode::ViewOnly` keeps everything else from the server
+    let (touch_display, touch_range): (Receiver<Touch>, _) = match touch::record_screen(config.touch_input.to_string()) {
+        Ok(recorded) => recorded,
+        Err(error) if config.view_only => {
+            warn!("cannot record the touch screen: {}", error);
+            (mpsc::channel().1, None) // no-op; never sending anything
+        }
+        Err(error) => return Err(error),
+    };
+    let mut transform = TouchTransform::new(fb.as_ref(), touch_range);
+    let mut gestures = Gestures::new(&config.gestures);
+    let mut stylus = Stylus::new(&config.stylus);
 
-    let touch_enabled: bool = !config.view_only;
-    let touch_display: Receiver<Touch> = if touch_enabled {
-        touch::record_screen(config.touch_input.to_string())
+    let keyboard_events = if !config.keyboards.is_empty() {
+        keyboard::record_keyboards(&config.keyboards)?
     } else {
-        mpsc::channel().1 // no-op; never sending anything
+        mpsc::channel().1
     };
-    let mut last_button: u8 = MOUSE_UNKNOWN;
+    let mut keyboard = Keyboard::new(config.keyboard_layout);
+    let mut auto_rotate = if CURRENT_DEVICE.has_gyroscope() { config.auto_rotate } else { AutoRotate::Off };
+    let device_paths: Vec<String> = config.buttons_input.iter()
+        .chain(config.gyroscope_input.iter().filter(|_| auto_rotate != AutoRotate::Off))
+        .cloned()
+        .collect();
+    let (device_input, device_events) = if !device_paths.is_empty() {
+        hardware::record_device(&device_paths, fb.as_ref())?
+    } else {
+        (mpsc::channel().0, mpsc::channel().1)
+    };
+    let mut page_turn = PageTurn::new(&config.page_turn);
+    let mut onscreen = OnScreenKeyboard::new();
+    let mut input_mode = if config.view_only { InputMode::ViewOnly } else { InputMode::Full };
+    let mut menu = Menu::new(config.processing, input_mode, auto_rotate);
+    // a non-incremental update was held back while the desktop was being resized
+    let mut stale = false;
+    // the token of the latest screen update, which continuous updates wait for
+    let mut busy: Option<u32> = None;
+    // kept across iterations: a session lost while asleep is only reconnected when waking up
+    let mut connected = true;
+    // asleep, the session is kept: its events are handled without drawing until waking up
+    let mut asleep = false;
 
     'running: loop {
         let time_at_sol = Instant::now();
+        let mut incremental = true;
+        let mut resized = false;
 
-        for touch in touch_display.try_iter() {
-            last_button = mouse_btn_to_vnc(touch.button).unwrap_or(last_button);
-            touch::touch_vnc(vnc, touch, last_button);
+        let mut pointers = Vec::new();
+        let mut menu_actions = Vec::new();
+        if asleep {
+            touch_display.try_iter().for_each(drop); // discard touches made while asleep
+            keyboard_events.try_iter().for_each(drop);
+        }
+        'touches: for mut touch in touch_display.try_iter() {
+            touch.position = transform.apply(&touch.position);
+            touch.fingers.iter_mut().for_each(|finger| *finger = transform.apply(finger));
+            touch.pen = touch.pen.map(|pen| transform.apply(&pen));
+            // the menu on top takes the touches on it first, then the on-screen keyboard, which
+            // turns them into key events
+            let (touches, actions) = menu.touch(fb, &shadow, touch);
+            menu_actions.extend(actions);
+            if input_mode == InputMode::ViewOnly {
+                continue;
+            }
+            let mut pointer_touches = Vec::new();
+            for touch in touches {
+                let (touches, keys) = onscreen.touch(fb, &shadow, touch);
+                pointer_touches.extend(touches);
+                for (down, keysym) in keys {
+                    if let Err(error) = vnc.send_key_event(down, keysym) {
+                        error!("cannot send key: {}", error);
+                        connected = false;
+                        break 'touches;
+                    }
+                }
+            }
+            for touch in pointer_touches {
+                if touch.pen.is_none() && !stylus.in_range() {
+                    if input_mode == InputMode::PenOnly {
+                        continue;
+                    }
+                    // fingers make gestures; they are ignored while the stylus is near, as the palm rests
+                    pointers.extend(gestures.touch(&touch, Instant::now()));
+                    continue;
+                }
+                let output = stylus.touch(&touch);
+                for (down, keysym) in output.keys {
+                    if let Err(error) = vnc.send_key_event(down, keysym) {
+                        error!("cannot send key: {}", error);
+                        connected = false;
+                        break 'touches;
+                    }
+                }
+                if output.refresh {
+                    info!("full update due to the stylus button");
+                    incremental = false;
+                }
+                pointers.extend(output.pointer);
+            }
+        }
+        pointers.extend(gestures.tick(Instant::now()));
+        for (buttons, position) in pointers {
+            if !connected {
+                break;
+            }
+            if let Err(error) = vnc.send_pointer_event(buttons, position.x as u16, position.y as u16) {
+                error!("cannot send touch: {}", error);
+                connected = false;
+            }
+            cursor.move_to(fb, &shadow, &overlays(&onscreen, &menu), pt!(position.x, position.y));
+        }
+
+        for action in menu_actions {
+            match action {
+                MenuAction::Processing(processing) => {
+                    post_proc_bin = PostProcBin::new(&processing);
+                    if let Some(lut) = &mut lut {
+                        lut.reprocess(&post_proc_bin); // servers don't send the colour map again
+                    }
+                    // the desktop is drawn again from scratch, processed the new way
+                    shadow = Shadow::new(width, height, draw::util::samples());
+                    draw.dirty_rects.clear();
+                    incremental = false;
+                }
+                MenuAction::Refresh => draw.full_refresh(fb),
+                MenuAction::ToggleInverted => {
+                    fb.toggle_inverted();
+                    draw.full_refresh(fb);
+                }
+                MenuAction::ToggleDithered => {
+                    fb.toggle_dithered();
+                    draw.full_refresh(fb);
+                }
+                MenuAction::ToggleMonochrome => {
+                    fb.toggle_monochrome();
+                    draw.full_refresh(fb);
+                }
+                MenuAction::Input(mode) => {
+                    info!("input mode {:?}", mode);
+                    input_mode = mode;
+                }
+                MenuAction::AutoRotate(mode) => auto_rotate = mode,
+                MenuAction::Disconnect => {
+                    info!("disconnecting");
+                    vnc.disconnect();
+                    break 'running;
+                }
+            }
+        }
+
+        for event in keyboard_events.try_iter() {
+            if input_mode == InputMode::ViewOnly {
+                continue;
+            }
+            if let Some((down, keysym)) = keyboard.handle(&event) {
+                if let Err(error) = vnc.send_key_event(down, keysym) {
+                    error!("cannot send key: {}", error);
+                    connected = false;
+                    break;
+                }
+            }
+        }
+
+        let mut sleeping = false;
+        let mut waking = false;
+        let mut rotation = None;
+        'buttons: for event in device_events.try_iter() {
+            if asleep {
+                waking |= hardware::is_wake(&event);
+                continue;
+            }
+            if hardware::is_sleep(&event) {
+                sleeping = true;
+                break;
+            }
+            if let DeviceEvent::RotateScreen(_) = event {
+                rotation = auto_rotate.rotation(&event, fb.rotation()).or(rotation);
+                continue;
+            }
+            if let DeviceEvent::Button { code: ButtonCode::Home, status: ButtonStatus::Pressed, .. } = event {
+                menu.toggle(fb, &shadow);
+                continue;
+            }
+            if input_mode == InputMode::ViewOnly {
+                continue;
+            }
+            for (down, keysym) in page_turn.handle(&event, Instant::now()) {
+                if let Err(error) = vnc.send_key_event(down, keysym) {
+                    error!("cannot send key: {}", error);
+                    connected = false;
+                    break 'buttons;
+                }
+            }
+        }
+
+        if let Some(rotation) = rotation {
+            info!("rotating the screen to {}", rotation);
+            match fb.set_rotation(rotation) {
+                Ok(_) => {
+                    transform = TouchTransform::new(fb.as_ref(), touch_range);
+                    device_input.send(display_rotate_event(rotation)).ok(); // the page turn buttons follow
+                    onscreen.rotate(fb.as_ref());
+                    // the whole screen is drawn again, with a full refresh
+                    shadow = Shadow::new(width, height, draw::util::samples());
+                    draw.dirty_rects.clear();
+                    draw.has_drawn_once = false;
+                    fb.clear(WHITE);
+                    cursor.invalidate();
+                    incremental = false;
+                    if config.connection.resize {
+                        if let Err(error) = fit_desktop(vnc, fb.as_ref()) {
+                            error!("cannot resize the desktop: {}", error);
+                            connected = false;
+                        }
+                    }
+                }
+                Err(error) => warn!("cannot rotate the screen: {:#}", error),
+            }
         }
 
         for event in vnc.poll_iter() {
             use client::Event;
 
+            if !connected {
+                break; // lost while asleep, reconnected when waking up
+            }
+
             match event {
-                Event::Disconnected(None) => break 'running,
+                Event::Disconnected(None) => {
+                    info!("server disconnected");
+                    connected = false;
+                    break;
+                }
                 Event::Disconnected(Some(error)) => {
                     error!("server disconnected: {:?}", error);
-                    break 'running;
+                    connected = false;
+                    break;
                 }
                 Event::PutPixels(vnc_rect, ref pixels) => {
                     debug!("Put pixels");
 
                     let elapsed_ms = time_at_sol.elapsed().as_millis();
                     debug!("network Δt: {}", elapsed_ms);
-                    let processed= processing::streamline_pixel_color(pixels, &post_proc_bin);
-                    let map = draw::util::to_map(&vnc_rect, &processed);
-                    debug!(
-                        "Put pixels w={} h={} w*h={} size={}",
-                        map.width,
-                        map.height,
-                        map.width * map.height,
-                        map.data.len()
-                    );
+                    let processed = processing::process_pixels(pixels, lut.as_ref(), &format, &post_proc_bin);
                     let elapsed_ms = time_at_sol.elapsed().as_millis();
                     debug!("postproc Δt: {}", elapsed_ms);
 
-                    let delta = draw::util::to_delta_map(&vnc_rect);
-                    draw::kobo::set_pixel_map_ro(fb, &delta, &map);
-                    let elapsed_ms = time_at_sol.elapsed().as_millis();
-                    debug!("draw Δt: {}", elapsed_ms);
+                    // only what differs from the screen is drawn, sparing e-ink refreshes for no-op updates
+                    let changed = match shadow.put(&vnc_rect, &processed) {
+                        Some(changed) => changed,
+                        None => {
+                            debug!("Put pixels w={} h={} unchanged", vnc_rect.width, vnc_rect.height);
+                            continue;
+                        }
+                    };
+                    debug!(
+                        "Put pixels w={} h={} changed w={} h={}",
+                        vnc_rect.width,
+                        vnc_rect.height,
+                        changed.width,
+                        changed.height
+                    );
 
-                    let delta_rect = draw::util::to_delta_rect(&vnc_rect);
-                    let fb_rect = rect![0, 0, width as i32, height as i32];
-                    if delta_rect == fb_rect {
-                        draw.update(fb, fb_rect);
-                    } else {
-                        draw::push_to_dirty_rect_list(&mut draw.dirty_rects, delta_rect);
-                    }
+                    // drawn at the end of the frame, along with the rest of it
+                    let delta_rect = draw::util::to_delta_rect(&changed);
+                    draw::push_to_dirty_rect_list(&mut draw.dirty_rects, delta_rect);
                     let elapsed_ms = time_at_sol.elapsed().as_millis();
                     debug!("rects Δt: {}", elapsed_ms);
                 }
                 Event::CopyPixels { src, dst } => {
                     debug!("Copy pixels!");
 
-                    #[cfg(feature = "eink_device")]
-                    {
-                        let src_left = src.left as u32;
-                        let src_top = src.top as u32;
-                        let mut intermediary_pixmap = Pixmap::new(
-                            dst.width as u32,
-                            dst.height as u32,
-                            CURRENT_DEVICE.color_samples(),
-                        );
-                        for y in 0..intermediary_pixmap.height {
-                            for x in 0..intermediary_pixmap.width {
-                                //let color = fb.get_pixel(src_left + x, src_top + y);
-                                //intermediary_pixmap.set_pixel(x, y, color);
-                            }
-                        }
-                        let delta = draw::util::to_delta_map(&dst);
-                        draw::kobo::set_pixel_map(fb, &delta, &intermediary_pixmap);
+                    if shadow.copy(&src, &dst) {
+                        let delta_rect = draw::util::to_delta_rect(&dst);
+                        draw::push_to_dirty_rect_list(&mut draw.dirty_rects, delta_rect);
+                    }
+                }
+                Event::Resize(new_width, new_height) => {
+                    info!("desktop resized to {}x{}", new_width, new_height);
+                    (width, height) = (new_width, new_height);
+                    shadow = Shadow::new(width, height, draw::util::samples());
+                    draw.dirty_rects.clear();
+                    draw.has_drawn_once = false; // full refresh with the first frame of the new size
+                    if !asleep {
+                        fb.clear(WHITE);
+                    }
+                    cursor.invalidate();
+                    incremental = false;
+                    resized = true;
+                }
+                Event::SetColourMap { first_colour, colours } => {
+                    debug!("Set colour map {}+{}", first_colour, colours.len());
+                    if let Some(lut) = &mut lut {
+                        lut.set_colours(first_colour, &colours, &post_proc_bin);
+                        incremental = false; // pixels drawn with the old colours are stale
+                    }
+                }
+                Event::SetCursor { size, hotspot, pixels, mask_bits } => {
+                    debug!("Set cursor {}x{}", size.0, size.1);
+                    let pixels = processing::process_pixels(&pixels, lut.as_ref(), &format, &post_proc_bin);
+                    let shape = CursorShape { size, hotspot, pixels, mask: mask_bits };
+                    if asleep {
+                        cursor.keep_shape(shape);
+                    } else {
+                        cursor.set_shape(fb, &shadow, &overlays(&onscreen, &menu), shape);
                     }
-
-                    let delta_rect = draw::util::to_delta_rect(&dst);
-                    draw::push_to_dirty_rect_list(&mut draw.dirty_rects, delta_rect);
                 }
                 Event::EndOfFrame => {
                     debug!("End of frame!");
-                    draw.draw_end(fb);
+                    if asleep {
+                        // the sleep screen stays, the desktop is drawn again in full when waking up
+                        draw.dirty_rects.clear();
+                        updates.frame_drawn();
+                        continue;
+                    }
+                    // the pixels of the whole frame reach the framebuffer together, to be refreshed in one batch
+                    for dr in &draw.dirty_rects {
+                        shadow.draw(fb, &draw::util::to_vnc_rect(dr));
+                    }
+                    let elapsed_ms = time_at_sol.elapsed().as_millis();
+                    debug!("draw Δt: {}", elapsed_ms);
+                    cursor.redraw(fb, &shadow, &overlays(&onscreen, &menu), &draw.dirty_rects);
+                    onscreen.redraw(fb, &draw.dirty_rects);
+                    menu.redraw(fb, &draw.dirty_rects);
+                    let desktop = rect![0, 0, width as i32, height as i32];
+                    if shadow.synced && draw.dirty_rects.contains(&desktop) {
+                        draw.update(fb, desktop);
+                    } else if shadow.synced {
+                        draw.draw_end(fb, &shadow);
+                    } else {
+                        // the first complete frame of this desktop, covering what is left of an earlier one
+                        shadow.synced = true;
+                        let screen = fb.rect();
+                        onscreen.redraw(fb, &[screen]);
+                        menu.redraw(fb, &[screen]);
+                        draw.update(fb, screen);
+                    }
+                    updates.frame_drawn();
                 }
                 // x => info!("{:?}", x), /* ignore unsupported events */
                 _ => (),
@@ -137,11 +444,13 @@ pub fn run(vnc: &mut Client, fb: &mut Box<dyn Framebuffer>, config: &Config) ->
         }
 
         if FRAME_MS > time_at_sol.elapsed().as_millis() as u64 {
-            if draw.dirty_rects_since_refresh.len() > 0
-                && draw.time_at_last_draw.elapsed().as_secs() > 3
-            {
+            if !asleep && draw.idle_refresh_due() {
                 draw.refresh(fb);
             }
+            if !asleep && page_turn.settled(draw.time_at_last_draw, Instant::now()) {
+                info!("full refresh of the turned page");
+                draw.full_refresh(fb);
+            }
             if FRAME_MS > time_at_sol.elapsed().as_millis() as u64 {
                 thread::sleep(Duration::from_millis(
                     FRAME_MS - time_at_sol.elapsed().as_millis() as u64,
@@ -154,18 +463,141 @@ pub fn run(vnc: &mut Client, fb: &mut Box<dyn Framebuffer>, config: &Config) ->
             );
         }
 
-        vnc.request_update(
-            Rect {
-                left: 0,
-                top: 0,
-                width,
-                height,
-            },
-            true,
-        )
-        .unwrap();
+        if connected {
+            if let Err(error) = poll_extensions(vnc, &mut updates, full_rect((width, height)), resized) {
+                error!("cannot answer the server: {}", error);
+                connected = false;
+            }
+        }
+
+        if connected && !asleep {
+            let rect = full_rect((width, height));
+            // updates pause in the iteration that started a refresh, and resume in a later one,
+            // once the screen is done with it
+            let started = draw.take_update();
+            let refreshing = started.is_some();
+            if refreshing {
+                busy = started;
+            } else if let Some(token) = busy.take().filter(|_| !updates.polling()) {
+                fb.wait(token).ok();
+            }
+            stale |= !incremental;
+            match request_updates(vnc, &mut updates, rect, refreshing, !stale) {
+                Ok(requested) => stale &= !requested,
+                Err(error) => {
+                    error!("cannot request updates: {}", error);
+                    connected = false;
+                }
+            }
+        }
+
+        if sleeping {
+            info!("going to sleep");
+            asleep = true;
+            if connected && config.sleep_disconnect {
+                vnc.disconnect();
+                connected = false;
+            } else if connected {
+                if let Err(error) = updates.sleep(vnc, full_rect((width, height)), true) {
+                    error!("cannot stop updates: {}", error);
+                    connected = false;
+                }
+            }
+            draw::status::draw_sleeping(fb);
+        }
+
+        let mut woke_up = false;
+        if waking {
+            info!("waking up");
+            asleep = false;
+            woke_up = true;
+            touch_display.try_iter().for_each(drop); // discard touches made while asleep
+            keyboard_events.try_iter().for_each(drop);
+            device_events.try_iter().for_each(drop);
+
+            // the sleep screen covers everything, so the next frame is drawn in full
+            shadow = Shadow::new(width, height, draw::util::samples());
+            draw.dirty_rects.clear();
+            draw.has_drawn_once = false;
+            fb.clear(WHITE);
+            cursor.invalidate();
+            if connected {
+                if let Err(error) = wake_updates(vnc, &mut updates, full_rect((width, height))) {
+                    error!("cannot resume updates: {}", error);
+                    connected = false;
+                }
+            }
+        }
+
+        if !connected && !asleep {
+            if !config.reconnect && !woke_up {
+                break 'running;
+            }
+            warn!("connection lost, reconnecting");
+            *vnc = connect(config, fb)?;
+            (width, height) = vnc.size();
+            shadow = Shadow::new(width, height, draw::util::samples());
+            format = vnc.format();
+            lut = PixelLut::for_format(&format, &post_proc_bin);
+            cursor = Cursor::new();
+            updates = ContinuousUpdates::new();
+            stale = false; // the handshake requested the whole desktop
+            draw.dirty_rects.clear();
+            touch_display.try_iter().for_each(drop); // discard touches made while offline
+            keyboard_events.try_iter().for_each(drop);
+            device_events.try_iter().for_each(drop);
+            keyboard.release_all(); // the new session has no keys held
+            stylus.release_all();
+            connected = true;
+        }
+    }
+
+    Ok(())
+}
+
+/// The screen areas of the menu and on-screen keyboard, which the cursor stays below.
+fn overlays(onscreen: &OnScreenKeyboard, menu: &Menu) -> Vec<Rectangle> {
+    onscreen.overlay().into_iter().chain(menu.overlay()).collect()
+}
+
+/// Handles the extension messages of the server, asleep as well.
+fn poll_extensions(vnc: &mut Session, updates: &mut ContinuousUpdates, rect: Rect, resized: bool) -> Result<(), Error> {
+    while let Some(extension) = vnc.poll_extension() {
+        match extension {
+            Extension::ExtendedDesktopSize { reason, status, .. } => vnc.handle_desktop_size(reason, status)?,
+            extension => updates.handle(vnc, extension, rect)?,
+        }
+    }
+    updates.answer_fences(vnc)?;
+    if resized {
+        updates.resize(vnc, rect)?;
+    }
+    Ok(())
+}
+
+/// Requests the next frame, or with continuous updates, paces the server by pausing them while
+/// the screen refreshes. A non-incremental request is always sent when the screen is stale.
+/// Nothing is requested while the server resizes the desktop, which may not fit the screen yet;
+/// returns whether the requests are up to date.
+fn request_updates(vnc: &mut Session, updates: &mut ContinuousUpdates, rect: Rect, refreshing: bool, incremental: bool) -> Result<bool, Error> {
+    if refreshing {
+        updates.pause(vnc, rect)?;
+    } else {
+        updates.resume(vnc, rect)?;
+    }
+    if vnc.resizing() {
+        return Ok(false);
     }
+    if updates.polling() || !incremental {
+        vnc.request_update(rect, incremental)?;
+    }
+    Ok(true)
+}
 
+/// Restarts updates after sleep, starting with the whole desktop.
+fn wake_updates(vnc: &mut Session, updates: &mut ContinuousUpdates, rect: Rect) -> Result<(), Error> {
+    updates.sleep(vnc, rect, false)?;
+    vnc.request_update(rect, false)?;
     Ok(())
 }
 
@@ -0,0 +1,422 @@
+use display::color::{BLACK, WHITE};
+use display::device::CURRENT_DEVICE;
+use display::framebuffer::{Framebuffer, UpdateMode};
+use display::geom::{BorderSpec, CornerSpec, Point, Rectangle};
+use display::{pt, rect};
+
+use crate::draw::shadow::Shadow;
+use crate::draw::text::{draw_text, text_size, GLYPH_HEIGHT};
+use crate::draw::util;
+use crate::hardware::AutoRotate;
+use crate::processing::PostProcConfig;
+use crate::touch::Touch;
+
+/// Which inputs are sent to the server.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InputMode {
+    /// Fingers, the stylus and keyboards.
+    Full,
+    /// The stylus and keyboards; fingers are ignored, e.g. so the palm can rest on the screen.
+    PenOnly,
+    /// Nothing but the menu.
+    ViewOnly,
+}
+
+impl InputMode {
+    fn label(self) -> &'static str {
+        match self {
+            InputMode::Full => "touch and pen",
+            InputMode::PenOnly => "pen only",
+            InputMode::ViewOnly => "view only",
+        }
+    }
+
+    fn next(self) -> InputMode {
+        match self {
+            InputMode::Full => InputMode::PenOnly,
+            InputMode::PenOnly => InputMode::ViewOnly,
+            InputMode::ViewOnly => InputMode::Full,
+        }
+    }
+}
+
+/// What the menu asks the session to do.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MenuAction {
+    /// The post processing changed; the desktop has to be processed and drawn again.
+    Processing(PostProcConfig),
+    /// Redraws the whole screen with the full waveform.
+    Refresh,
+    ToggleInverted,
+    ToggleDithered,
+    ToggleMonochrome,
+    Input(InputMode),
+    AutoRotate(AutoRotate),
+    Disconnect,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Item {
+    Contrast,
+    GrayPoint,
+    WhiteCutoff,
+    Inverted,
+    Dithered,
+    Monochrome,
+    Input,
+    Rotation,
+    Refresh,
+    Disconnect,
+    Close,
+}
+
+impl Item {
+    fn label(self) -> &'static str {
+        match self {
+            Item::Contrast => "Contrast",
+            Item::GrayPoint => "Gray point",
+            Item::WhiteCutoff => "White cutoff",
+            Item::Inverted => "Inverted",
+            Item::Dithered => "Dithered",
+            Item::Monochrome => "Monochrome",
+            Item::Input => "Input",
+            Item::Rotation => "Rotation",
+            Item::Refresh => "Full refresh",
+            Item::Disconnect => "Disconnect",
+            Item::Close => "Close",
+        }
+    }
+
+    /// Whether the item is adjusted with - and + buttons, instead of tapped.
+    fn adjustable(self) -> bool {
+        matches!(self, Item::Contrast | Item::GrayPoint | Item::WhiteCutoff)
+    }
+}
+
+enum Tracking {
+    Idle,
+    /// A finger went down in the top left corner; held back until it's either a tap or a drag.
+    Corner { held: Vec<Touch> },
+    /// A finger went down on the menu, on the target with this index.
+    Target(Option<usize>),
+    /// The touch was consumed; ignore it until the finger is lifted.
+    Ignore,
+    Pointer,
+}
+
+/// The settings of the session, drawn over the middle of the remote screen. It's opened by a tap
+/// in the top left corner or the home button, and closed by a tap outside of it.
+pub struct Menu {
+    visible: bool,
+    area: Rectangle,
+    /// Where to tap for what: an item, and -1 or +1 for the buttons of adjustable ones.
+    targets: Vec<(Rectangle, Item, i32)>,
+    tracking: Tracking,
+    processing: PostProcConfig,
+    input: InputMode,
+    auto_rotate: AutoRotate,
+}
+
+impl Menu {
+    pub fn new(processing: PostProcConfig, input: InputMode, auto_rotate: AutoRotate) -> Menu {
+        Menu {
+            visible: false,
+            area: rect![0, 0, 0, 0],
+            targets: Vec::new(),
+            tracking: Tracking::Idle,
+            processing,
+            input,
+            auto_rotate,
+        }
+    }
+
+    /// Routes a touch either to the menu, returning what it asks for, or onwards, returning the
+    /// touches for the keyboard and the server.
+    pub fn touch(&mut self, fb: &mut Box<dyn Framebuffer>, shadow: &Shadow, touch: Touch) -> (Vec<Touch>, Vec<MenuAction>) {
+        let position = pt!(touch.position.x, touch.position.y);
+        let released = touch.button == Some(0);
+        let tracking = std::mem::replace(&mut self.tracking, Tracking::Idle);
+        match tracking {
+            Tracking::Idle if touch.button == Some(1) => {
+                if self.visible && self.area.includes(position) {
+                    let index = self.targets.iter().position(|(rect, _, _)| rect.includes(position));
+                    if let Some(index) = index {
+                        let target = self.targets[index].0;
+                        fb.invert_region(&target);
+                        fb.update(&target, UpdateMode::FastMono).ok();
+                    }
+                    self.tracking = Tracking::Target(index);
+                } else if self.visible {
+                    self.hide(fb, shadow);
+                    self.tracking = Tracking::Ignore;
+                } else if in_corner(position) {
+                    self.tracking = Tracking::Corner { held: vec![touch] };
+                } else {
+                    self.tracking = Tracking::Pointer;
+                    return (vec![touch], Vec::new());
+                }
+                (Vec::new(), Vec::new())
+            }
+            Tracking::Idle => (vec![touch], Vec::new()),
+            Tracking::Corner { mut held } => {
+                if released && in_corner(position) {
+                    self.show(fb);
+                    return (Vec::new(), Vec::new());
+                }
+                held.push(touch);
+                if released {
+                    return (held, Vec::new());
+                }
+                if in_corner(position) {
+                    self.tracking = Tracking::Corner { held };
+                    return (Vec::new(), Vec::new());
+                }
+                // dragged out of the corner, it was meant for the server
+                self.tracking = Tracking::Pointer;
+                (held, Vec::new())
+            }
+            Tracking::Target(index) if released => {
+                let Some((_, item, step)) = index.map(|index| self.targets[index]) else {
+                    return (Vec::new(), Vec::new());
+                };
+                let action = self.apply(item, step);
+                if item == Item::Close || item == Item::Disconnect {
+                    self.hide(fb, shadow);
+                } else {
+                    self.draw(fb);
+                    fb.update(&self.area, UpdateMode::Gui).ok();
+                }
+                (Vec::new(), action.into_iter().collect())
+            }
+            Tracking::Target(index) => {
+                self.tracking = Tracking::Target(index);
+                (Vec::new(), Vec::new())
+            }
+            Tracking::Ignore => {
+                if !released {
+                    self.tracking = Tracking::Ignore;
+                }
+                (Vec::new(), Vec::new())
+            }
+            Tracking::Pointer => {
+                if !released {
+                    self.tracking = Tracking::Pointer;
+                }
+                (vec![touch], Vec::new())
+            }
+        }
+    }
+
+    /// Opens the menu, or closes it when it's open, e.g. for the home button.
+    pub fn toggle(&mut self, fb: &mut Box<dyn Framebuffer>, shadow: &Shadow) {
+        if self.visible {
+            self.hide(fb, shadow);
+        } else {
+            self.show(fb);
+        }
+    }
+
+    /// Draws the menu again, if `dirty` painted the remote screen over it.
+    pub fn redraw(&mut self, fb: &mut Box<dyn Framebuffer>, dirty: &[Rectangle]) {
+        if self.visible && dirty.iter().any(|rect| rect.overlaps(&self.area)) {
+            self.draw(fb);
+        }
+    }
+
+    /// The screen area the menu covers while it's visible.
+    pub fn overlay(&self) -> Option<Rectangle> {
+        self.visible.then_some(self.area)
+    }
+
+    pub fn show(&mut self, fb: &mut Box<dyn Framebuffer>) {
+        self.visible = true;
+        self.draw(fb);
+        fb.update(&self.area, UpdateMode::Gui).ok();
+    }
+
+    /// Closes the menu and restores the remote screen below it.
+    pub fn hide(&mut self, fb: &mut Box<dyn Framebuffer>, shadow: &Shadow) {
+        if !self.visible {
+            return;
+        }
+        self.visible = false;
+        self.tracking = Tracking::Idle;
+        fb.draw_rectangle(&self.area, WHITE);
+        let desktop = rect![0, 0, shadow.width as i32, shadow.height as i32];
+        if let Some(below) = self.area.intersection(&desktop) {
+            shadow.draw(fb, &util::to_vnc_rect(&below));
+        }
+        fb.update(&self.area, UpdateMode::Partial).ok();
+    }
+
+    /// The items, in the order they are listed.
+    fn items(&self) -> Vec<Item> {
+        let mut items = vec![
+            Item::Contrast,
+            Item::GrayPoint,
+            Item::WhiteCutoff,
+            Item::Inverted,
+            Item::Dithered,
+            Item::Monochrome,
+            Item::Input,
+        ];
+        if self.auto_rotate != AutoRotate::Off {
+            items.push(Item::Rotation);
+        }
+        items.extend([Item::Refresh, Item::Disconnect, Item::Close]);
+        items
+    }
+
+    /// Changes the setting of `item`, by `step` for adjustable ones.
+    fn apply(&mut self, item: Item, step: i32) -> Option<MenuAction> {
+        let processing = &mut self.processing;
+        match item {
+            Item::Contrast => {
+                processing.contrast_exp = ((processing.contrast_exp * 10.0).round() + step as f32).clamp(1.0, 40.0) / 10.0;
+            }
+            Item::GrayPoint => {
+                processing.contrast_gray_point = (processing.contrast_gray_point + 8.0 * step as f32).clamp(0.0, 255.0);
+            }
+            Item::WhiteCutoff => {
+                processing.white_cutoff = (processing.white_cutoff as i32 + 8 * step).clamp(0, 255) as u8;
+            }
+            Item::Inverted => return Some(MenuAction::ToggleInverted),
+            Item::Dithered => return Some(MenuAction::ToggleDithered),
+            Item::Monochrome => return Some(MenuAction::ToggleMonochrome),
+            Item::Input => {
+                self.input = self.input.next();
+                return Some(MenuAction::Input(self.input));
+            }
+            Item::Rotation => {
+                self.auto_rotate.toggle_lock();
+                return Some(MenuAction::AutoRotate(self.auto_rotate));
+            }
+            Item::Refresh => return Some(MenuAction::Refresh),
+            Item::Disconnect => return Some(MenuAction::Disconnect),
+            Item::Close => return None,
+        }
+        Some(MenuAction::Processing(self.processing))
+    }
+
+    fn value(&self, fb: &dyn Framebuffer, item: Item) -> String {
+        let on_off = |enabled: bool| if enabled { "on" } else { "off" }.to_string();
+        match item {
+            Item::Contrast => format!("{:.1}", self.processing.contrast_exp),
+            Item::GrayPoint => format!("{}", self.processing.contrast_gray_point.round()),
+            Item::WhiteCutoff => format!("{}", self.processing.white_cutoff),
+            Item::Inverted => on_off(fb.inverted()),
+            Item::Dithered => on_off(fb.dithered()),
+            Item::Monochrome => on_off(fb.monochrome()),
+            Item::Input => self.input.label().to_string(),
+            Item::Rotation if self.auto_rotate == AutoRotate::Locked => "locked".to_string(),
+            Item::Rotation => "auto".to_string(),
+            Item::Refresh | Item::Disconnect | Item::Close => String::new(),
+        }
+    }
+
+    /// Lays the menu out for the current screen and draws it.
+    fn draw(&mut self, fb: &mut Box<dyn Framebuffer>) {
+        let items = self.items();
+        let (width, height) = (fb.width() as i32, fb.height() as i32);
+        let row_height = (CURRENT_DEVICE.dpi as i32 / 4).min(height / (items.len() as i32 + 1)).max(GLYPH_HEIGHT + 4);
+        let padding = row_height / 4;
+        let panel_width = (12 * row_height).min(width - 2 * padding);
+        let panel_height = items.len() as i32 * row_height + 2 * padding;
+        let left = (width - panel_width) / 2;
+        let top = (height - panel_height) / 2;
+        self.area = rect![left, top, left + panel_width, top + panel_height];
+
+        let border = (padding / 4).max(1);
+        fb.draw_rounded_rectangle_with_border(
+            &self.area,
+            &CornerSpec::Uniform(padding),
+            &BorderSpec { thickness: border as u16, color: BLACK },
+            &WHITE,
+        );
+
+        let scale = (row_height / 3 / GLYPH_HEIGHT).max(1);
+        let (_, text_height) = text_size("0", scale);
+        self.targets.clear();
+        for (row, &item) in items.iter().enumerate() {
+            let y = top + padding + row as i32 * row_height;
+            let baseline = y + (row_height - text_height) / 2;
+            let (min_x, max_x) = (left + padding, left + panel_width - padding);
+            if item.adjustable() {
+                draw_text(fb, item.label(), pt!(min_x, baseline), scale, BLACK);
+                let minus = rect![max_x - 3 * row_height, y + border, max_x - 2 * row_height, y + row_height - border];
+                let plus = rect![max_x - row_height, y + border, max_x, y + row_height - border];
+                draw_button(fb, &minus, "-", scale);
+                draw_button(fb, &plus, "+", scale);
+                let value = self.value(fb.as_ref(), item);
+                let (value_width, _) = text_size(&value, scale);
+                draw_text(fb, &value, pt!(max_x - 3 * row_height / 2 - value_width / 2, baseline), scale, BLACK);
+                self.targets.push((minus, item, -1));
+                self.targets.push((plus, item, 1));
+            } else {
+                let target = rect![min_x, y + border, max_x, y + row_height - border];
+                let value = self.value(fb.as_ref(), item);
+                if value.is_empty() {
+                    draw_button(fb, &target, item.label(), scale);
+                } else {
+                    draw_text(fb, item.label(), pt!(min_x, baseline), scale, BLACK);
+                    let (value_width, _) = text_size(&value, scale);
+                    draw_text(fb, &value, pt!(max_x - value_width, baseline), scale, BLACK);
+                }
+                self.targets.push((target, item, 0));
+            }
+        }
+    }
+}
+
+/// Draws a rounded button with a centered `label`.
+fn draw_button(fb: &mut Box<dyn Framebuffer>, rect: &Rectangle, label: &str, scale: i32) {
+    let gap = (rect.height() as i32 / 16).max(1);
+    fb.draw_rounded_rectangle_with_border(
+        rect,
+        &CornerSpec::Uniform(2 * gap),
+        &BorderSpec { thickness: (gap / 2).max(1) as u16, color: BLACK },
+        &WHITE,
+    );
+    let (width, height) = text_size(label, scale);
+    let origin = pt!(rect.min.x + (rect.width() as i32 - width) / 2, rect.min.y + (rect.height() as i32 - height) / 2);
+    draw_text(fb, label, origin, scale, BLACK);
+}
+
+/// Whether `position` is in the top left corner that opens the menu, about 6mm square.
+fn in_corner(position: Point) -> bool {
+    let size = (CURRENT_DEVICE.dpi as i32 / 4).max(16);
+    position.x < size && position.y < size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn menu() -> Menu {
+        let processing = PostProcConfig { contrast_exp: 1.0, contrast_gray_point: 224.0, white_cutoff: 250 };
+        Menu::new(processing, InputMode::Full, AutoRotate::Off)
+    }
+
+    #[test]
+    fn adjusts_processing_within_bounds() {
+        let mut menu = menu();
+        let Some(MenuAction::Processing(processing)) = menu.apply(Item::Contrast, 1) else { panic!("no processing") };
+        assert_eq!(processing.contrast_exp, 1.1);
+        menu.apply(Item::WhiteCutoff, 1);
+        let Some(MenuAction::Processing(processing)) = menu.apply(Item::WhiteCutoff, 1) else { panic!("no processing") };
+        assert_eq!(processing.white_cutoff, 255, "clamped");
+        assert_eq!(processing.contrast_exp, 1.1, "kept");
+    }
+
+    #[test]
+    fn cycles_input_modes_and_lists_rotation_only_when_automatic() {
+        let mut menu = menu();
+        assert_eq!(menu.apply(Item::Input, 0), Some(MenuAction::Input(InputMode::PenOnly)));
+        assert_eq!(menu.apply(Item::Input, 0), Some(MenuAction::Input(InputMode::ViewOnly)));
+        assert_eq!(menu.apply(Item::Input, 0), Some(MenuAction::Input(InputMode::Full)));
+        assert!(!menu.items().contains(&Item::Rotation));
+        menu.auto_rotate = AutoRotate::On;
+        assert_eq!(menu.apply(Item::Rotation, 0), Some(MenuAction::AutoRotate(AutoRotate::Locked)));
+        assert!(menu.items().contains(&Item::Rotation));
+    }
+}
@@ -1,5 +1,9 @@
 #![allow(unused)]
 
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use display::color::Color;
+use vnc::{Colour, PixelFormat};
+
 #[repr(align(256))]
 pub struct PostProcBin {
     pub data: [u8; 256],
@@ -43,24 +47,226 @@ impl PostProcBin {
     }
 }
 
-pub fn streamline_pixel_color(pixels: &Vec<u8>, post_proc: &PostProcBin) -> Vec<u8> {
-    if display::device::CURRENT_DEVICE.color_samples() == 1 {
-        pixels
-            .chunks(4)
-            .map(|chunks| {
-                let rgb =display::color::Color::from_rgb(chunks);
-                rgb.gray()
-            })
+/// Converts server pixels through the 8 bit `lut` if there is one, according to `format` otherwise.
+pub fn process_pixels(pixels: &[u8], lut: Option<&PixelLut>, format: &PixelFormat, post_proc: &PostProcBin) -> Vec<u8> {
+    match lut {
+        Some(lut) => lut.apply(pixels),
+        None => streamline_pixel_color(pixels, format, post_proc),
+    }
+}
+
+/// Converts server pixels in `format` to what the framebuffer gets drawn from:
+/// post processed gray levels, or BGRx on color devices.
+pub fn streamline_pixel_color(pixels: &[u8], format: &PixelFormat, post_proc: &PostProcBin) -> Vec<u8> {
+    decode_pixels(pixels, format, post_proc, display::device::CURRENT_DEVICE.color_samples())
+}
+
+fn decode_pixels(pixels: &[u8], format: &PixelFormat, post_proc: &PostProcBin, color_samples: usize) -> Vec<u8> {
+    let bytes = (format.bits_per_pixel as usize / 8).max(1);
+    let rgb = pixels.chunks_exact(bytes).map(|pixel| pixel_rgb(pixel, format));
+    if color_samples == 1 {
+        rgb.map(|[red, green, blue]| Color::Rgb(red, green, blue).gray())
             .map(|c| post_proc.data[c as usize])
             .collect()
     } else {
-        pixels.iter().map(|&f| f).collect()
+        rgb.flat_map(|[red, green, blue]| [blue, green, red, 0]).collect()
+    }
+}
+
+/// The color of a true colour `pixel`, which is as wide as `format.bits_per_pixel`.
+fn pixel_rgb(pixel: &[u8], format: &PixelFormat) -> [u8; 3] {
+    let value = match (pixel.len(), format.big_endian) {
+        (2, false) => LittleEndian::read_u16(pixel) as u32,
+        (2, true) => BigEndian::read_u16(pixel) as u32,
+        (4, false) => LittleEndian::read_u32(pixel),
+        (4, true) => BigEndian::read_u32(pixel),
+        _ => pixel[0] as u32,
+    };
+    [
+        channel(value, format.red_shift, format.red_max),
+        channel(value, format.green_shift, format.green_max),
+        channel(value, format.blue_shift, format.blue_max),
+    ]
+}
+
+/// Scales the channel at `shift` with the maximum `max` to 0..=255.
+fn channel(value: u32, shift: u8, max: u16) -> u8 {
+    (((value >> shift) & max as u32) * 255).checked_div(max as u32).unwrap_or(0) as u8
+}
+
+/// The processed output for every 8 bit pixel value, i.e. colour map indices or 8bpp true colour.
+/// Post processing is applied once per entry, instead of once per pixel.
+pub struct PixelLut {
+    /// The colour of every pixel value, kept to process the table again.
+    rgb: [[u8; 3]; 256],
+    /// Whether pixel values index a colour map, whose grays are reduced to the 16 levels of the e-ink panel.
+    colour_map: bool,
+    gray: [u8; 256],
+    bgrx: [[u8; 4]; 256],
+}
+
+impl PixelLut {
+    /// A table for 8 bit formats, `None` for wider ones. Colour map entries are white until
+    /// the server sent them with `set_colours`.
+    pub fn for_format(format: &PixelFormat, post_proc: &PostProcBin) -> Option<PixelLut> {
+        if format.bits_per_pixel != 8 {
+            return None;
+        }
+        let mut lut = PixelLut {
+            rgb: [[255; 3]; 256],
+            colour_map: !format.true_colour,
+            gray: [255; 256],
+            bgrx: [[255, 255, 255, 0]; 256],
+        };
+        if format.true_colour {
+            for value in 0..=255u8 {
+                lut.set(value as usize, pixel_rgb(&[value], format), post_proc);
+            }
+        }
+        Some(lut)
+    }
+
+    /// Applies SetColourMapEntries; the grays are reduced to the 16 levels of the e-ink panel.
+    pub fn set_colours(&mut self, first_colour: u16, colours: &[Colour], post_proc: &PostProcBin) {
+        for (index, colour) in (first_colour as usize..256).zip(colours) {
+            let rgb = [(colour.red >> 8) as u8, (colour.green >> 8) as u8, (colour.blue >> 8) as u8];
+            self.set(index, rgb, post_proc);
+        }
+    }
+
+    /// Processes the table again with `post_proc`, keeping the colours the server sent.
+    pub fn reprocess(&mut self, post_proc: &PostProcBin) {
+        for index in 0..256 {
+            self.set(index, self.rgb[index], post_proc);
+        }
+    }
+
+    fn set(&mut self, index: usize, rgb: [u8; 3], post_proc: &PostProcBin) {
+        let gray = post_proc.data[Color::from_rgb(&rgb).gray() as usize];
+        self.rgb[index] = rgb;
+        self.gray[index] = if self.colour_map { (gray as u16 * 15 / 255 * 17) as u8 } else { gray };
+        self.bgrx[index] = [rgb[2], rgb[1], rgb[0], 0];
+    }
+
+    /// Same as `streamline_pixel_color`, for 8 bit pixels.
+    pub fn apply(&self, pixels: &[u8]) -> Vec<u8> {
+        if display::device::CURRENT_DEVICE.color_samples() == 1 {
+            pixels.iter().map(|&p| self.gray[p as usize]).collect()
+        } else {
+            pixels.iter().flat_map(|&p| self.bgrx[p as usize]).collect()
+        }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct PostProcConfig {
     pub contrast_exp: f32,
     pub contrast_gray_point: f32,
     pub white_cutoff: u8,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vnc::ColorFormat;
+
+    fn neutral() -> PostProcBin {
+        PostProcBin::new(&PostProcConfig { contrast_exp: 1.0, contrast_gray_point: 224.0, white_cutoff: 255 })
+    }
+
+    fn rgb888(big_endian: bool) -> PixelFormat {
+        PixelFormat {
+            bits_per_pixel: 32,
+            depth: 24,
+            big_endian,
+            true_colour: true,
+            red_max: 255,
+            green_max: 255,
+            blue_max: 255,
+            red_shift: 16,
+            green_shift: 8,
+            blue_shift: 0,
+        }
+    }
+
+    fn rgb565(big_endian: bool) -> PixelFormat {
+        PixelFormat {
+            bits_per_pixel: 16,
+            depth: 16,
+            big_endian,
+            true_colour: true,
+            red_max: 31,
+            green_max: 63,
+            blue_max: 31,
+            red_shift: 11,
+            green_shift: 5,
+            blue_shift: 0,
+        }
+    }
+
+    #[test]
+    fn decode_32bpp() {
+        let orange_le = [0x00, 0x80, 0xff, 0x00];
+        let orange_be = [0x00, 0xff, 0x80, 0x00];
+        assert_eq!(decode_pixels(&orange_le, &rgb888(false), &neutral(), 4), [0x00, 0x80, 0xff, 0], "little endian");
+        assert_eq!(decode_pixels(&orange_be, &rgb888(true), &neutral(), 4), [0x00, 0x80, 0xff, 0], "big endian");
+        assert_eq!(decode_pixels(&orange_le, &rgb888(false), &neutral(), 1), [145], "gray");
+    }
+
+    #[test]
+    fn decode_16bpp() {
+        let red_green_blue_le = [0x00, 0xf8, 0xe0, 0x07, 0x1f, 0x00];
+        let red_green_blue_be = [0xf8, 0x00, 0x07, 0xe0, 0x00, 0x1f];
+        let bgrx = [0, 0, 255, 0, 0, 255, 0, 0, 255, 0, 0, 0];
+        assert_eq!(decode_pixels(&red_green_blue_le, &rgb565(false), &neutral(), 4), bgrx, "little endian");
+        assert_eq!(decode_pixels(&red_green_blue_be, &rgb565(true), &neutral(), 4), bgrx, "big endian");
+        assert_eq!(decode_pixels(&[0xff, 0xff], &rgb565(false), &neutral(), 1), [255], "white");
+    }
+
+    #[test]
+    fn decode_8bpp() {
+        let format = ColorFormat::Bgr233.pixel_format().unwrap();
+        assert_eq!(decode_pixels(&[0b00_000_111, 0xff], &format, &neutral(), 4), [0, 0, 255, 0, 255, 255, 255, 0]);
+        assert_eq!(decode_pixels(&[0b00_000_111, 0xff], &format, &neutral(), 1), [54, 255]);
+    }
+
+    #[test]
+    fn bgr233_lut() {
+        let format = ColorFormat::Bgr233.pixel_format().unwrap();
+        let lut = PixelLut::for_format(&format, &neutral()).unwrap();
+        assert_eq!(lut.gray[0x00], 0, "black");
+        assert_eq!(lut.gray[0xff], 255, "white");
+        assert_eq!(lut.bgrx[0b00_000_111], [0, 0, 255, 0], "red");
+        assert_eq!(lut.bgrx[0b00_111_000], [0, 255, 0, 0], "green");
+        assert_eq!(lut.bgrx[0b11_000_000], [255, 0, 0, 0], "blue");
+        let wide = PixelFormat { bits_per_pixel: 32, ..format };
+        assert!(PixelLut::for_format(&wide, &neutral()).is_none(), "only for 8 bit formats");
+    }
+
+    #[test]
+    fn gray16_lut() {
+        let format = ColorFormat::Gray16.pixel_format().unwrap();
+        let lut = PixelLut::for_format(&format, &neutral()).unwrap();
+        assert_eq!(&lut.gray[0..3], &[0x00, 0x11, 0x22]);
+        assert_eq!(lut.gray[8], 0x88);
+        assert_eq!(lut.gray[15], 0xff, "white");
+    }
+
+    #[test]
+    fn colour_map_to_16_grays() {
+        let format = PixelFormat { true_colour: false, ..ColorFormat::Bgr233.pixel_format().unwrap() };
+        let mut lut = PixelLut::for_format(&format, &neutral()).unwrap();
+        assert_eq!(lut.gray[3], 255, "white until the colour map arrives");
+
+        let gray = |level: u16| Colour { red: level, green: level, blue: level };
+        lut.set_colours(2, &[gray(0), gray(0x8000), gray(0xffff)], &neutral());
+        assert_eq!(&lut.gray[2..5], &[0x00, 0x77, 0xff]);
+        assert_eq!(lut.gray[5], 255, "untouched");
+
+        let cutoff = PostProcBin::new(&PostProcConfig { contrast_exp: 1.0, contrast_gray_point: 224.0, white_cutoff: 100 });
+        lut.reprocess(&cutoff);
+        assert_eq!(&lut.gray[2..5], &[0x00, 0xff, 0xff], "the colours are kept");
+        lut.reprocess(&neutral());
+        assert_eq!(&lut.gray[2..6], &[0x00, 0x77, 0xff, 0xff]);
+    }
+}
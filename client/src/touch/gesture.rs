@@ -0,0 +1,263 @@
+use std::time::{Duration, Instant};
+
+use display::device::CURRENT_DEVICE;
+
+use crate::touch::{Coord, Touch, MOUSE_LEFT, MOUSE_RIGHT, MOUSE_UNKNOWN};
+
+const WHEEL_UP: u8 = 1 << 3;
+const WHEEL_DOWN: u8 = 1 << 4;
+const WHEEL_LEFT: u8 = 1 << 5;
+const WHEEL_RIGHT: u8 = 1 << 6;
+
+/// Thresholds of the finger gestures; distances in millimeters, converted with the screen's dpi.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GestureConfig {
+    /// How far a finger may move and still tap.
+    pub tap_slop_mm: f32,
+    /// How long a still finger is held for a right click.
+    pub long_press_ms: u64,
+    /// How soon after a tap the finger has to come down again to drag with the left button.
+    pub double_tap_ms: u64,
+    /// How far two fingers move per wheel click.
+    pub scroll_step_mm: f32,
+}
+
+impl Default for GestureConfig {
+    fn default() -> GestureConfig {
+        GestureConfig {
+            tap_slop_mm: 2.0,
+            long_press_ms: 600,
+            double_tap_ms: 300,
+            scroll_step_mm: 5.0,
+        }
+    }
+}
+
+/// A pointer event for the server: the button mask and the position.
+pub type Pointer = (u8, Coord);
+
+enum State {
+    Idle,
+    /// One finger down that hasn't moved yet; `drag` if it came down right after a tap.
+    Pending { start: Coord, since: Instant, drag: bool },
+    /// One finger moving the pointer, dragging with `buttons` held.
+    Moving { buttons: u8 },
+    /// Two or more fingers, scrolling whenever their center moves a step away from `anchor`.
+    Scrolling { anchor: Coord, fingers: usize, scrolled: bool },
+    /// The gesture is complete; waiting for all fingers to be lifted.
+    Done,
+}
+
+/// Turns finger touches into pointer events:
+/// - a tap clicks, a long press right clicks
+/// - a drag moves the pointer, unless it starts with a tap right before, then it drags with the left button
+/// - two fingers scroll with the wheel buttons 4 to 7 when moved, and right click when tapped
+pub struct Gestures {
+    slop: i32,
+    long_press: Duration,
+    double_tap: Duration,
+    scroll_step: i32,
+    state: State,
+    last_tap: Option<(Instant, Coord)>,
+}
+
+impl Gestures {
+    pub fn new(config: &GestureConfig) -> Gestures {
+        Gestures::with_dpi(config, CURRENT_DEVICE.dpi)
+    }
+
+    fn with_dpi(config: &GestureConfig, dpi: u16) -> Gestures {
+        let pixels = |mm: f32| ((mm * dpi as f32 / 25.4).round() as i32).max(1);
+        Gestures {
+            slop: pixels(config.tap_slop_mm),
+            long_press: Duration::from_millis(config.long_press_ms),
+            double_tap: Duration::from_millis(config.double_tap_ms),
+            scroll_step: pixels(config.scroll_step_mm),
+            state: State::Idle,
+            last_tap: None,
+        }
+    }
+
+    /// The pointer events for `touch`, which arrived at `now`.
+    pub fn touch(&mut self, touch: &Touch, now: Instant) -> Vec<Pointer> {
+        let fingers = touch.fingers.len();
+        let position = touch.position;
+        let state = std::mem::replace(&mut self.state, State::Idle);
+        let mut events = Vec::new();
+        self.state = match state {
+            State::Idle | State::Done if fingers == 0 => State::Idle,
+            State::Idle if fingers == 1 => {
+                let drag = self.last_tap.is_some_and(|(time, tap)| {
+                    now.duration_since(time) <= self.double_tap && distance(tap, position) <= 3 * self.slop
+                });
+                events.push((MOUSE_UNKNOWN, position));
+                State::Pending { start: position, since: now, drag }
+            }
+            State::Pending { start, .. } if fingers == 0 => {
+                click(&mut events, MOUSE_LEFT, start);
+                self.last_tap = Some((now, start));
+                State::Idle
+            }
+            State::Pending { start, since, drag } if fingers == 1 => {
+                if distance(start, position) <= self.slop {
+                    State::Pending { start, since, drag }
+                } else if drag {
+                    events.push((MOUSE_LEFT, start));
+                    events.push((MOUSE_LEFT, position));
+                    State::Moving { buttons: MOUSE_LEFT }
+                } else {
+                    events.push((MOUSE_UNKNOWN, position));
+                    State::Moving { buttons: MOUSE_UNKNOWN }
+                }
+            }
+            State::Idle | State::Pending { .. } => {
+                State::Scrolling { anchor: center(&touch.fingers), fingers, scrolled: false }
+            }
+            State::Moving { .. } if fingers == 0 => {
+                events.push((MOUSE_UNKNOWN, position));
+                State::Idle
+            }
+            State::Moving { buttons } if fingers == 1 || buttons != MOUSE_UNKNOWN => {
+                events.push((buttons, position));
+                State::Moving { buttons }
+            }
+            State::Moving { .. } => State::Scrolling { anchor: center(&touch.fingers), fingers, scrolled: false },
+            State::Scrolling { anchor, scrolled, .. } if fingers == 0 => {
+                if !scrolled {
+                    click(&mut events, MOUSE_RIGHT, anchor);
+                }
+                State::Idle
+            }
+            State::Scrolling { anchor, fingers: before, scrolled } => {
+                let now_center = center(&touch.fingers);
+                if fingers != before {
+                    // a finger came or went, moving the center without the hand moving
+                    State::Scrolling { anchor: now_center, fingers, scrolled }
+                } else {
+                    let (anchor, wheeled) = self.scroll(&mut events, anchor, now_center);
+                    State::Scrolling { anchor, fingers, scrolled: scrolled || wheeled }
+                }
+            }
+            State::Done => State::Done,
+        };
+        events
+    }
+
+    /// The pointer events of gestures completed by time alone, i.e. a long press.
+    pub fn tick(&mut self, now: Instant) -> Vec<Pointer> {
+        let mut events = Vec::new();
+        if let State::Pending { start, since, drag: false } = self.state {
+            if now.duration_since(since) >= self.long_press {
+                click(&mut events, MOUSE_RIGHT, start);
+                self.state = State::Done;
+            }
+        }
+        events
+    }
+
+    /// Clicks the wheel for each step the fingers moved; the content follows the fingers.
+    fn scroll(&self, events: &mut Vec<Pointer>, mut anchor: Coord, center: Coord) -> (Coord, bool) {
+        let mut wheeled = false;
+        let position = anchor;
+        while (center.y - anchor.y).abs() >= self.scroll_step {
+            let downwards = center.y > anchor.y;
+            click(events, if downwards { WHEEL_UP } else { WHEEL_DOWN }, position);
+            anchor.y += if downwards { self.scroll_step } else { -self.scroll_step };
+            wheeled = true;
+        }
+        while (center.x - anchor.x).abs() >= self.scroll_step {
+            let rightwards = center.x > anchor.x;
+            click(events, if rightwards { WHEEL_LEFT } else { WHEEL_RIGHT }, position);
+            anchor.x += if rightwards { self.scroll_step } else { -self.scroll_step };
+            wheeled = true;
+        }
+        (anchor, wheeled)
+    }
+}
+
+fn click(events: &mut Vec<Pointer>, buttons: u8, position: Coord) {
+    events.push((buttons, position));
+    events.push((MOUSE_UNKNOWN, position));
+}
+
+fn center(fingers: &[Coord]) -> Coord {
+    let count = fingers.len().max(1) as i32;
+    Coord {
+        x: fingers.iter().map(|finger| finger.x).sum::<i32>() / count,
+        y: fingers.iter().map(|finger| finger.y).sum::<i32>() / count,
+    }
+}
+
+fn distance(a: Coord, b: Coord) -> i32 {
+    (a.x - b.x).abs().max((a.y - b.y).abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn touch(fingers: &[(i32, i32)]) -> Touch {
+        let fingers: Vec<Coord> = fingers.iter().map(|&(x, y)| Coord { x, y }).collect();
+        Touch {
+            position: fingers.first().copied().unwrap_or(Coord { x: 0, y: 0 }),
+            pressure: 0,
+            timestamp: Utc::now(),
+            distance: None,
+            button: None,
+            stylus_back: None,
+            stylus_side: None,
+            stylus_tilt: None,
+            fingers,
+            pen: None,
+        }
+    }
+
+    fn gestures() -> Gestures {
+        // 1mm is 10 pixels at 254 dpi
+        let config = GestureConfig { tap_slop_mm: 1.0, long_press_ms: 500, double_tap_ms: 300, scroll_step_mm: 2.0 };
+        Gestures::with_dpi(&config, 254)
+    }
+
+    fn buttons(events: Vec<Pointer>) -> Vec<u8> {
+        events.into_iter().map(|(buttons, _)| buttons).collect()
+    }
+
+    #[test]
+    fn tap_long_press_and_drag() {
+        let mut gestures = gestures();
+        let start = Instant::now();
+        gestures.touch(&touch(&[(100, 100)]), start);
+        assert_eq!(buttons(gestures.touch(&touch(&[]), start)), vec![MOUSE_LEFT, 0], "tap");
+
+        let later = start + Duration::from_secs(1);
+        gestures.touch(&touch(&[(100, 100)]), later);
+        assert!(gestures.tick(later + Duration::from_millis(100)).is_empty());
+        assert_eq!(buttons(gestures.tick(later + Duration::from_millis(500))), vec![MOUSE_RIGHT, 0], "long press");
+        assert!(gestures.touch(&touch(&[]), later).is_empty());
+
+        let tapped = later + Duration::from_secs(1);
+        gestures.touch(&touch(&[(100, 100)]), tapped);
+        gestures.touch(&touch(&[]), tapped);
+        let held = tapped + Duration::from_millis(200);
+        gestures.touch(&touch(&[(102, 100)]), held);
+        assert!(gestures.tick(held + Duration::from_secs(1)).is_empty(), "no right click when held for a drag");
+        assert_eq!(buttons(gestures.touch(&touch(&[(150, 100)]), held)), vec![MOUSE_LEFT, MOUSE_LEFT]);
+        assert_eq!(buttons(gestures.touch(&touch(&[]), held)), vec![0], "released");
+    }
+
+    #[test]
+    fn two_fingers_scroll_or_right_click() {
+        let mut gestures = gestures();
+        let now = Instant::now();
+        gestures.touch(&touch(&[(100, 100)]), now);
+        gestures.touch(&touch(&[(100, 100), (120, 100)]), now);
+        assert_eq!(buttons(gestures.touch(&touch(&[]), now)), vec![MOUSE_RIGHT, 0], "two finger tap");
+
+        gestures.touch(&touch(&[(100, 100), (120, 100)]), now);
+        assert_eq!(buttons(gestures.touch(&touch(&[(100, 55), (120, 55)]), now)), vec![WHEEL_DOWN, 0, WHEEL_DOWN, 0], "fingers up scroll down");
+        assert_eq!(buttons(gestures.touch(&touch(&[(125, 55), (145, 55)]), now)), vec![WHEEL_LEFT, 0]);
+        assert_eq!(buttons(gestures.touch(&touch(&[(125, 55)]), now)), vec![], "lifting one finger doesn't scroll");
+        assert!(gestures.touch(&touch(&[]), now).is_empty(), "no right click after scrolling");
+    }
+}
@@ -1,14 +1,15 @@
 use std::{fs::File, str::FromStr, result};
 
 use chrono::{DateTime, Duration, Utc};
-use evdev_rs::{Device, InputEvent, ReadFlag, ReadStatus};
+use evdev_rs::enums::{EventCode, EV_ABS};
+use evdev_rs::{Device, DeviceWrapper, InputEvent, ReadFlag, ReadStatus};
 
 /// Describes a touch event.
 #[derive(Debug, Clone)]
 pub struct Touch {
     /// The touch position
     pub position: Coord,
-    /// The touch pressure
+    /// The touch pressure, the last one reported while in contact, 0 otherwise
     pub pressure: i32,
     /// The timestamp of the touch event
     pub timestamp: DateTime<Utc>,
@@ -17,17 +18,40 @@ pub struct Touch {
     pub stylus_back: Option<i32>,
     pub stylus_side: Option<i32>,
     pub stylus_tilt: Option<Coord>,
+    /// The positions of all fingers on the screen, one per multi-touch slot.
+    pub fingers: Vec<Coord>,
+    /// Where the stylus is while it's in range, touching or hovering.
+    pub pen: Option<Coord>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Coord {
     pub x: i32,
     pub y: i32,
 }
 
+/// A multi-touch slot: where its contact was last, whether it's down, and whether it's a stylus.
+#[derive(Debug, Copy, Clone)]
+struct Slot {
+    position: Coord,
+    down: bool,
+    pen: bool,
+}
+
+const EMPTY_SLOT: Slot = Slot { position: Coord { x: 0, y: 0 }, down: false, pen: false };
+
+/// `ABS_MT_TOOL_TYPE` of a stylus.
+const MT_TOOL_PEN: i32 = 1;
+
 /// Blocking event listener for touch events
 pub struct TouchEventListener {
     device: Device,
+    /// The multi-touch slot that position events refer to.
+    slot: usize,
+    slots: Vec<Slot>,
+    /// Whether the device reports tracking ids; otherwise BTN_TOUCH tells if the single finger is down.
+    tracking: bool,
+    pressure: i32,
 }
 
 impl TouchEventListener {
@@ -37,13 +61,12 @@ impl TouchEventListener {
         let touch_path: String = std::env::var("KOBO_TS_INPUT")
             .or(String::from_str("/dev/input/event1"))
             .unwrap();
-        let device = Self::open_device(touch_path)?;
-        return Ok(Self { device })
+        Self::open_input(touch_path)
     }
 
     pub fn open_input(touch_path: String) -> std::io::Result<Self> {
         let device = Self::open_device(touch_path)?;
-        Ok(Self { device })
+        Ok(Self { device, slot: 0, slots: vec![EMPTY_SLOT], tracking: false, pressure: 0 })
     }
 
     fn open_device(path: String) -> std::io::Result<Device> {
@@ -52,6 +75,14 @@ impl TouchEventListener {
         Device::new_from_file(file)
     }
 
+    /// The largest x and y the device reports, from its multi-touch or else single touch axes.
+    pub fn range(&self) -> Option<(i32, i32)> {
+        let max = |code: EV_ABS| self.device.abs_info(&EventCode::EV_ABS(code)).map(|info| info.maximum);
+        max(EV_ABS::ABS_MT_POSITION_X)
+            .zip(max(EV_ABS::ABS_MT_POSITION_Y))
+            .or_else(|| max(EV_ABS::ABS_X).zip(max(EV_ABS::ABS_Y)))
+    }
+
     /// Read the next event from the stream
     pub fn next_raw_event(&self) -> std::io::Result<(ReadStatus, InputEvent)> {
         self.device
@@ -66,16 +97,13 @@ impl TouchEventListener {
     ///
     /// Pressure is currently unreliable, so we'll just assume it's always down.
     pub fn next_touch(
-        &self,
+        &mut self,
         timeout: Option<Duration>,
     ) -> Option<Touch> {
         // Keep track of the start time
         let start = Utc::now();
 
         // Holder for out data
-        let mut x = None;
-        let mut y = None;
-        let mut pressure = None;
         let mut button: Option<i32> = None;
         let mut stylus_back: Option<i32> = None;
         let mut stylus_side: Option<i32> = None;
@@ -103,23 +131,32 @@ impl TouchEventListener {
                 // We are looking for ABS touch events
                 match event.event_code {
                     evdev_rs::enums::EventCode::EV_ABS(kind) => match kind {
-                        evdev_rs::enums::EV_ABS::ABS_X => {
-                            x = Some(event.value);
-                        }
-                        evdev_rs::enums::EV_ABS::ABS_Y => {
-                            y = Some(event.value);
-                        }
+                        evdev_rs::enums::EV_ABS::ABS_X |
                         evdev_rs::enums::EV_ABS::ABS_MT_POSITION_X => {
-                            x = Some(event.value);
+                            self.slots[self.slot].position.x = event.value;
                         }
+                        evdev_rs::enums::EV_ABS::ABS_Y |
                         evdev_rs::enums::EV_ABS::ABS_MT_POSITION_Y => {
-                            y = Some(event.value);
+                            self.slots[self.slot].position.y = event.value;
+                        }
+                        evdev_rs::enums::EV_ABS::ABS_MT_SLOT => {
+                            self.slot = event.value.max(0) as usize;
+                            if self.slots.len() <= self.slot {
+                                self.slots.resize(self.slot + 1, EMPTY_SLOT);
+                            }
+                        }
+                        evdev_rs::enums::EV_ABS::ABS_MT_TRACKING_ID => {
+                            self.tracking = true;
+                            self.slots[self.slot].down = event.value >= 0;
+                        }
+                        evdev_rs::enums::EV_ABS::ABS_MT_TOOL_TYPE => {
+                            self.slots[self.slot].pen = event.value == MT_TOOL_PEN;
                         }
                         evdev_rs::enums::EV_ABS::ABS_PRESSURE => {
-                            pressure = Some(event.value);
+                            self.pressure = event.value;
                         }
                         evdev_rs::enums::EV_ABS::ABS_MT_PRESSURE => {
-                            pressure = Some(event.value);
+                            self.pressure = event.value;
                         }
                         evdev_rs::enums::EV_ABS::ABS_MT_DISTANCE => {
                             distance = Some(event.value);
@@ -135,6 +172,21 @@ impl TouchEventListener {
                     evdev_rs::enums::EventCode::EV_KEY(kind) => match kind {
                         evdev_rs::enums::EV_KEY::BTN_TOUCH => {
                             button = Some(event.value);
+                            if event.value == 0 {
+                                // a lifted stylus stays in range, hovering
+                                self.slots.iter_mut().filter(|slot| !slot.pen).for_each(|slot| slot.down = false);
+                                self.pressure = 0;
+                            } else if !self.tracking {
+                                self.slots[0].down = true;
+                            }
+                        }
+                        evdev_rs::enums::EV_KEY::BTN_TOOL_PEN |
+                        evdev_rs::enums::EV_KEY::BTN_TOOL_RUBBER => {
+                            // single touch digitizers tell with these when the stylus comes into range
+                            self.slots[self.slot].pen = event.value == 1;
+                            if !self.tracking {
+                                self.slots[self.slot].down = event.value == 1;
+                            }
                         }
                         evdev_rs::enums::EV_KEY::BTN_STYLUS => {
                             stylus_back = Some(event.value);
@@ -156,16 +208,23 @@ impl TouchEventListener {
 
             // Check if we have all the data
             if syn.is_some() {
+                let fingers: Vec<Coord> = self.slots.iter().filter(|slot| slot.down && !slot.pen).map(|slot| slot.position).collect();
+                let pen = self.slots.iter().find(|slot| slot.down && slot.pen).map(|slot| slot.position);
+                // the stylus or the first finger, or where the last one was lifted
+                let position = pen.or(fingers.first().copied()).unwrap_or(self.slots[self.slot].position);
+
                 // Return the touch event
                 return Some(Touch {
-                    position: Coord{x: x.unwrap(), y: y.unwrap()},
-                    pressure: pressure.unwrap(),
+                    position,
+                    pressure: self.pressure,
                     timestamp: Utc::now(),
                     distance,
                     button,
                     stylus_back,
                     stylus_side,
-                    stylus_tilt: if tilt_x.is_some() && tilt_y.is_some() { Some(Coord{x: tilt_x.unwrap(), y: tilt_y.unwrap()}) } else { None }
+                    stylus_tilt: if tilt_x.is_some() && tilt_y.is_some() { Some(Coord{x: tilt_x.unwrap(), y: tilt_y.unwrap()}) } else { None },
+                    fingers,
+                    pen,
                 });
             }
         }
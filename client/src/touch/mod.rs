@@ -1,9 +1,15 @@
 #![allow(unused)]
 
+mod gesture;
 mod mouse;
 mod listener;
 mod screen;
+mod stylus;
+mod transform;
 
-pub use self::mouse::{MOUSE_LEFT, MOUSE_UNKNOWN, mouse_btn_to_vnc};
-pub use self::listener::{TouchEventListener, Touch};
-pub use self::screen::{record_screen, touch_vnc};
+pub use self::gesture::{GestureConfig, Gestures, Pointer};
+pub use self::mouse::{MOUSE_LEFT, MOUSE_MIDDLE, MOUSE_RIGHT, MOUSE_UNKNOWN, mouse_btn_to_vnc};
+pub use self::listener::{Coord, TouchEventListener, Touch};
+pub use self::screen::record_screen;
+pub use self::stylus::{Stylus, StylusAction, StylusConfig};
+pub use self::transform::TouchTransform;
@@ -1,4 +1,6 @@
 pub const MOUSE_LEFT: u8 = 0x01;
+pub const MOUSE_MIDDLE: u8 = 0x02;
+pub const MOUSE_RIGHT: u8 = 0x04;
 pub const MOUSE_UNKNOWN: u8 = 0x00;
 
 pub fn mouse_btn_to_vnc(button: Option<i32>) -> Option<u8> {
@@ -0,0 +1,213 @@
+use std::str::FromStr;
+
+use crate::keyboard::chord;
+use crate::touch::{mouse_btn_to_vnc, Pointer, Touch, MOUSE_LEFT, MOUSE_MIDDLE, MOUSE_RIGHT, MOUSE_UNKNOWN};
+
+/// What a stylus button does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StylusAction {
+    /// Holds mouse buttons while the stylus button is held.
+    Buttons(u8),
+    /// Presses the keys of a chord, e.g. `ctrl+z`, and releases them with the stylus button.
+    Keys(Vec<u32>),
+    /// Asks the server for a full update, to clean up the screen.
+    Refresh,
+    Nothing,
+}
+
+impl FromStr for StylusAction {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<StylusAction, String> {
+        match name {
+            "left" => Ok(StylusAction::Buttons(MOUSE_LEFT)),
+            "middle" => Ok(StylusAction::Buttons(MOUSE_MIDDLE)),
+            "right" => Ok(StylusAction::Buttons(MOUSE_RIGHT)),
+            "refresh" => Ok(StylusAction::Refresh),
+            "none" => Ok(StylusAction::Nothing),
+            _ => chord(name).map(StylusAction::Keys).ok_or_else(|| {
+                format!("unknown stylus action '{}', expected left, middle, right, refresh, none or a key chord like ctrl+z", name)
+            }),
+        }
+    }
+}
+
+/// How the stylus maps to the pointer. The Kobo styluses have two buttons, reported as
+/// `PEN_ERASE` and `PEN_HIGHLIGHT`; the latter is the side button.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StylusConfig {
+    /// The pressure below which the tip touching the screen doesn't click.
+    pub pressure_threshold: i32,
+    pub erase: StylusAction,
+    pub highlight: StylusAction,
+}
+
+impl Default for StylusConfig {
+    fn default() -> StylusConfig {
+        StylusConfig {
+            pressure_threshold: 0,
+            erase: StylusAction::Buttons(MOUSE_MIDDLE),
+            highlight: StylusAction::Buttons(MOUSE_RIGHT),
+        }
+    }
+}
+
+/// What to send the server for a stylus report.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct StylusOutput {
+    pub pointer: Option<Pointer>,
+    pub keys: Vec<(bool, u32)>,
+    pub refresh: bool,
+}
+
+/// Turns the reports of a stylus into pointer and key events: hovering moves the pointer,
+/// the tip clicks once pressed hard enough, and the buttons do their `StylusAction`.
+pub struct Stylus {
+    config: StylusConfig,
+    in_range: bool,
+    touching: bool,
+    /// Whether the erase and the highlight buttons are held.
+    held: [bool; 2],
+}
+
+impl Stylus {
+    pub fn new(config: &StylusConfig) -> Stylus {
+        Stylus {
+            config: config.clone(),
+            in_range: false,
+            touching: false,
+            held: [false; 2],
+        }
+    }
+
+    /// Whether the stylus was near the screen in the last report; touches go to it until it leaves.
+    pub fn in_range(&self) -> bool {
+        self.in_range
+    }
+
+    pub fn touch(&mut self, touch: &Touch) -> StylusOutput {
+        let mut output = StylusOutput::default();
+        let Some(position) = touch.pen else {
+            // out of range, let go of everything
+            self.in_range = false;
+            self.touching = false;
+            output.keys = self.release_all();
+            output.pointer = Some((MOUSE_UNKNOWN, touch.position));
+            return output;
+        };
+        self.in_range = true;
+        if let Some(button) = mouse_btn_to_vnc(touch.button) {
+            self.touching = button == MOUSE_LEFT;
+        }
+        if touch.distance.is_some_and(|distance| distance > 0) {
+            self.touching = false;
+        }
+
+        for (index, value) in [touch.stylus_back, touch.stylus_side].into_iter().enumerate() {
+            let Some(down) = value.map(|value| value != 0) else { continue };
+            if down == self.held[index] {
+                continue; // auto repeat
+            }
+            self.held[index] = down;
+            match self.action(index) {
+                StylusAction::Keys(keysyms) if down => output.keys.extend(keysyms.iter().map(|&keysym| (true, keysym))),
+                StylusAction::Keys(keysyms) => output.keys.extend(keysyms.iter().rev().map(|&keysym| (false, keysym))),
+                StylusAction::Refresh => output.refresh |= down,
+                StylusAction::Buttons(_) | StylusAction::Nothing => (),
+            }
+        }
+
+        let mut buttons = if self.touching && touch.pressure >= self.config.pressure_threshold {
+            MOUSE_LEFT
+        } else {
+            MOUSE_UNKNOWN
+        };
+        for index in 0..self.held.len() {
+            if let (true, StylusAction::Buttons(held)) = (self.held[index], self.action(index)) {
+                buttons |= held;
+            }
+        }
+        output.pointer = Some((buttons, position));
+        output
+    }
+
+    /// Releases the keys of held buttons, e.g. before the session is replaced.
+    pub fn release_all(&mut self) -> Vec<(bool, u32)> {
+        let mut keys = Vec::new();
+        for index in 0..self.held.len() {
+            if let (true, StylusAction::Keys(keysyms)) = (self.held[index], self.action(index)) {
+                keys.extend(keysyms.iter().rev().map(|&keysym| (false, keysym)));
+            }
+        }
+        self.held = [false; 2];
+        keys
+    }
+
+    fn action(&self, index: usize) -> &StylusAction {
+        if index == 0 {
+            &self.config.erase
+        } else {
+            &self.config.highlight
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::touch::Coord;
+    use chrono::Utc;
+
+    fn pen(button: Option<i32>, pressure: i32, distance: Option<i32>, buttons: (Option<i32>, Option<i32>)) -> Touch {
+        let position = Coord { x: 10, y: 20 };
+        Touch {
+            position,
+            pressure,
+            timestamp: Utc::now(),
+            distance,
+            button,
+            stylus_back: buttons.0,
+            stylus_side: buttons.1,
+            stylus_tilt: None,
+            fingers: Vec::new(),
+            pen: Some(position),
+        }
+    }
+
+    fn buttons(output: StylusOutput) -> u8 {
+        output.pointer.unwrap().0
+    }
+
+    #[test]
+    fn hovers_and_clicks_above_the_threshold() {
+        let config = StylusConfig { pressure_threshold: 100, ..StylusConfig::default() };
+        let mut stylus = Stylus::new(&config);
+        assert_eq!(buttons(stylus.touch(&pen(None, 0, Some(5), (None, None)))), MOUSE_UNKNOWN, "hovering");
+        assert_eq!(buttons(stylus.touch(&pen(Some(1), 50, Some(0), (None, None)))), MOUSE_UNKNOWN, "too light");
+        assert_eq!(buttons(stylus.touch(&pen(None, 150, None, (None, None)))), MOUSE_LEFT);
+        assert_eq!(buttons(stylus.touch(&pen(None, 150, None, (None, Some(1))))), MOUSE_LEFT | MOUSE_RIGHT, "side button");
+        assert_eq!(buttons(stylus.touch(&pen(Some(0), 0, Some(3), (None, None)))), MOUSE_RIGHT, "lifted");
+        assert!(stylus.in_range());
+
+        let mut gone = pen(None, 0, None, (None, None));
+        gone.pen = None;
+        assert_eq!(buttons(stylus.touch(&gone)), MOUSE_UNKNOWN);
+        assert!(!stylus.in_range());
+    }
+
+    #[test]
+    fn buttons_press_chords_and_refresh() {
+        let config = StylusConfig {
+            pressure_threshold: 0,
+            erase: "ctrl+z".parse().unwrap(),
+            highlight: StylusAction::Refresh,
+        };
+        let mut stylus = Stylus::new(&config);
+        let pressed = stylus.touch(&pen(None, 0, Some(5), (Some(1), Some(1))));
+        assert_eq!(pressed.keys, vec![(true, 0xffe3), (true, 'z' as u32)]);
+        assert!(pressed.refresh);
+        assert!(!stylus.touch(&pen(None, 0, Some(5), (Some(1), Some(1)))).refresh, "held, not repeated");
+        assert_eq!(stylus.release_all(), vec![(false, 'z' as u32), (false, 0xffe3)]);
+        assert!("hyper".parse::<StylusAction>().is_err());
+    }
+}
@@ -0,0 +1,91 @@
+use display::device::CURRENT_DEVICE;
+use display::framebuffer::Framebuffer;
+
+use crate::touch::Coord;
+
+/// Maps raw touch panel coordinates to screen pixels, like `display::input::parse_device_events`:
+/// the axes are swapped and mirrored as the device needs for the framebuffer rotation, and
+/// scaled from the range the panel reports to the screen size.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TouchTransform {
+    swap: bool,
+    mirror: (bool, bool),
+    /// The screen size, after rotation.
+    dims: (u32, u32),
+    /// The largest raw x and y, in the axes of the panel; `None` when it reports screen pixels.
+    range: Option<(i32, i32)>,
+}
+
+impl TouchTransform {
+    pub fn new(fb: &dyn Framebuffer, range: Option<(i32, i32)>) -> TouchTransform {
+        let rotation = fb.rotation();
+        TouchTransform {
+            swap: CURRENT_DEVICE.should_swap_axes(rotation),
+            mirror: CURRENT_DEVICE.should_mirror_axes(rotation),
+            dims: fb.dims(),
+            range,
+        }
+    }
+
+    pub fn apply(&self, raw: &Coord) -> Coord {
+        let (mut x, mut y) = if self.swap { (raw.y, raw.x) } else { (raw.x, raw.y) };
+        let (width, height) = (self.dims.0 as i32, self.dims.1 as i32);
+        if let Some(range) = self.range {
+            let (max_x, max_y) = if self.swap { (range.1, range.0) } else { range };
+            x = scale(x, max_x, width);
+            y = scale(y, max_y, height);
+        }
+        if self.mirror.0 {
+            x = width - 1 - x;
+        }
+        if self.mirror.1 {
+            y = height - 1 - y;
+        }
+        Coord {
+            x: x.clamp(0, (width - 1).max(0)),
+            y: y.clamp(0, (height - 1).max(0)),
+        }
+    }
+}
+
+/// Scales `value` of 0..=`max` to 0..`size`.
+fn scale(value: i32, max: i32, size: i32) -> i32 {
+    if max <= 0 {
+        return value;
+    }
+    (value as i64 * (size - 1) as i64 / max as i64) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transform(swap: bool, mirror: (bool, bool), range: Option<(i32, i32)>) -> TouchTransform {
+        TouchTransform { swap, mirror, dims: (1264, 1680), range }
+    }
+
+    fn apply(transform: TouchTransform, x: i32, y: i32) -> (i32, i32) {
+        let coord = transform.apply(&Coord { x, y });
+        (coord.x, coord.y)
+    }
+
+    #[test]
+    fn swaps_and_mirrors() {
+        assert_eq!(apply(transform(false, (false, false), None), 10, 20), (10, 20));
+        assert_eq!(apply(transform(true, (false, false), None), 10, 20), (20, 10));
+        assert_eq!(apply(transform(true, (true, false), None), 10, 20), (1243, 10));
+        assert_eq!(apply(transform(false, (false, true), None), 10, 20), (10, 1659));
+    }
+
+    #[test]
+    fn scales_the_panel_range() {
+        // a digitizer reporting 0..=4095 on both axes
+        let scaled = transform(false, (false, false), Some((4095, 4095)));
+        assert_eq!(apply(scaled, 0, 0), (0, 0));
+        assert_eq!(apply(scaled, 4095, 4095), (1263, 1679));
+        // the panel x axis becomes the screen y axis
+        let swapped = transform(true, (false, false), Some((1679 * 2, 1263 * 2)));
+        assert_eq!(apply(swapped, 1679 * 2, 1263 * 2), (1263, 1679));
+        assert_eq!(apply(swapped, 9999, -5), (0, 1679), "clamped to the screen");
+    }
+}
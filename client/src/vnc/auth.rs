@@ -1,38 +1,41 @@
 use vnc::client;
 use vnc::client::{AuthChoice, AuthMethod};
+use crate::error::Error;
 use crate::vnc::Connection;
 
-pub fn authenticate(con: &Connection, methods: &[AuthMethod]) -> Option<AuthChoice> {
+pub fn authenticate(con: &Connection, methods: &[AuthMethod]) -> Result<AuthChoice, Error> {
     debug!("available authentication methods: {:?}", methods);
+    let mut missing = None;
     for method in methods {
         match method {
-            client::AuthMethod::None => return Some(client::AuthChoice::None),
-            client::AuthMethod::Password => {
-                return match con.password {
-                    None => !panic!("VNC Auth not possible, due to missing 'password' arg"),
-                    Some(ref password) => {
-                        let mut key = [0; 8];
-                        for (i, byte) in password.bytes().enumerate() {
-                            if i == 8 {
-                                break;
-                            }
-                            key[i] = byte
+            client::AuthMethod::None => return Ok(client::AuthChoice::None),
+            client::AuthMethod::Password => match con.password {
+                None => missing = Some("VNC Auth not possible, due to missing 'password' arg"),
+                Some(password) => {
+                    let mut key = [0; 8];
+                    for (i, byte) in password.bytes().enumerate() {
+                        if i == 8 {
+                            break;
                         }
-                        Some(client::AuthChoice::Password(key))
+                        key[i] = byte
                     }
+                    return Ok(client::AuthChoice::Password(key));
                 }
-            }
+            },
             client::AuthMethod::AppleRemoteDesktop => match (con.username, con.password) {
                 (Some(username), Some(password)) => {
-                    return Some(client::AuthChoice::AppleRemoteDesktop(
+                    return Ok(client::AuthChoice::AppleRemoteDesktop(
                         username.to_owned(),
                         password.to_owned(),
                     ))
                 }
-                _ => (),
+                _ => missing = Some("Apple Remote Desktop Auth not possible, due to missing 'username' or 'password' arg"),
             },
             _ => (),
         }
     }
-    None
+    Err(Error::Auth(match missing {
+        Some(reason) => reason.to_string(),
+        None => format!("none of the offered methods {:?} is supported", methods),
+    }))
 }
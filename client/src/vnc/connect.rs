@@ -1,23 +1,45 @@
+use crate::error::Error;
 use crate::vnc::auth;
+use crate::vnc::filter::Filter;
+use crate::vnc::{ColorFormat, Session};
+#[cfg(feature = "tls")]
+use crate::vnc::vencrypt;
+use std::net::TcpStream;
 use vnc::{Client, Encoding, Rect};
 
-pub fn connect(con: Connection) -> Client {
+const ENCODING_CONTINUOUS_UPDATES: i32 = -313;
+const ENCODING_FENCE: i32 = -312;
+const ENCODING_EXTENDED_DESKTOP_SIZE: i32 = -308;
+
+pub fn connect(con: Connection) -> Result<Session, Error> {
     info!("connecting to {}:{}", con.host, con.port);
-    let stream = match std::net::TcpStream::connect((con.host, con.port)) {
-        Ok(stream) => stream,
-        Err(error) => {
-            error!("cannot connect to {}:{}: {}", con.host, con.port, error);
-            std::process::exit(1)
-        }
-    };
+    let stream = TcpStream::connect((con.host, con.port))
+        .map_err(Error::Connect)?;
+    handshake(stream, con)
+}
 
-    let mut vnc = match Client::from_tcp_stream(stream, !con.exclusive, |methods| auth::authenticate(&con, methods)) {
-        Ok(vnc) => vnc,
-        Err(error) => {
-            error!("cannot initialize VNC session: {}", error);
-            std::process::exit(1)
-        }
+/// Runs the RFB handshake on an established `stream`, regardless of which side opened it.
+pub(crate) fn handshake(stream: TcpStream, con: Connection) -> Result<Session, Error> {
+    let stream = if con.tls {
+        secure(stream, &con)?
+    } else {
+        stream
+    };
+    let (filter, stream, extensions) = if con.continuous_updates || con.resize {
+        let (filter, stream, extensions) = Filter::insert(stream).map_err(Error::Connect)?;
+        (Some(filter), stream, Some(extensions))
+    } else {
+        (None, stream, None)
     };
+    let raw = stream.try_clone().map_err(Error::Connect)?;
+
+    let mut auth_error = None;
+    let mut vnc = Client::from_tcp_stream(stream, !con.exclusive, |methods| {
+        auth::authenticate(&con, methods)
+            .map_err(|error| auth_error = Some(error))
+            .ok()
+    })
+    .map_err(|error| auth_error.take().unwrap_or_else(|| Error::from(error)))?;
 
     let (width, height) = vnc.size();
     info!(
@@ -30,13 +52,41 @@ pub fn connect(con: Connection) -> Client {
     let vnc_format = vnc.format();
     info!("received {:?}", vnc_format);
 
-    vnc.set_encodings(&[Encoding::CopyRect, Encoding::Zrle])
-        .unwrap();
+    let mut encodings = vec![Encoding::CopyRect, Encoding::Zrle, Encoding::DesktopSize];
+    if con.local_cursor {
+        encodings.push(Encoding::Cursor);
+    }
+    if let Some(filter) = &filter {
+        let requested = con.format.pixel_format();
+        filter.start(requested.map_or(vnc_format.bits_per_pixel, |format| format.bits_per_pixel), (width, height));
+    }
+    if con.continuous_updates {
+        encodings.push(Encoding::Unknown(ENCODING_CONTINUOUS_UPDATES));
+        encodings.push(Encoding::Unknown(ENCODING_FENCE));
+    }
+    if con.resize {
+        encodings.push(Encoding::Unknown(ENCODING_EXTENDED_DESKTOP_SIZE));
+    }
+    vnc.set_encodings(&encodings)?;
+
+    if let Some(format) = con.format.pixel_format() {
+        info!("requesting {:?} pixel format", con.format);
+        vnc.set_format(format)?;
+    }
+
+    vnc.request_update(full_rect(vnc.size()), false)?;
 
-    vnc.request_update(full_rect(vnc.size()), false)
-        .unwrap();
+    Ok(Session::new(vnc, raw, extensions))
+}
+
+#[cfg(feature = "tls")]
+fn secure(stream: TcpStream, con: &Connection) -> Result<TcpStream, Error> {
+    vencrypt::secure(stream, con)
+}
 
-    vnc
+#[cfg(not(feature = "tls"))]
+fn secure(_stream: TcpStream, _con: &Connection) -> Result<TcpStream, Error> {
+    Err(Error::Auth("VeNCrypt requested, but einkvnc was built without the 'tls' feature".to_string()))
 }
 
 fn full_rect(size: (u16,u16)) -> Rect {
@@ -55,4 +105,17 @@ pub struct Connection<'a> {
     pub username: Option<&'a str>,
     pub password: Option<&'a str>,
     pub exclusive: bool,
+    /// Use VeNCrypt; TLS encrypts the session and *Plain sub types send the untruncated password.
+    pub tls: bool,
+    /// CA certificate (PEM) to verify the server certificate with X509 sub types.
+    pub tls_ca: Option<&'a str>,
+    /// SHA-256 fingerprint of the server certificate; pins it instead of verifying a CA chain.
+    pub tls_fingerprint: Option<&'a str>,
+    pub format: ColorFormat,
+    /// Draw the pointer ourselves, instead of getting it painted into the desktop (Cursor pseudo-encoding).
+    pub local_cursor: bool,
+    /// Let the server push updates (ContinuousUpdates and Fence extensions) instead of polling for them.
+    pub continuous_updates: bool,
+    /// Let the desktop be resized to the screen (ExtendedDesktopSize extension).
+    pub resize: bool,
 }
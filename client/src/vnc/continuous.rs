@@ -0,0 +1,110 @@
+use std::collections::VecDeque;
+
+use vnc::Rect;
+
+use crate::error::Error;
+use crate::vnc::{Extension, Session};
+
+const FENCE_BLOCK_BEFORE: u32 = 1 << 0;
+const FENCE_REQUEST: u32 = 1 << 31;
+
+/// Client side of the ContinuousUpdates extension. Once the server announced it, updates are
+/// pushed instead of requested, and paused while the e-ink controller is busy refreshing.
+/// EnableContinuousUpdates is only sent when updates are to be switched on or off.
+/// Fence requests are answered once the frames before them are drawn, which honours BlockBefore.
+/// BlockAfter isn't, as later frames may be drawn before the answer, so the answer clears it.
+#[derive(Default)]
+pub struct ContinuousUpdates {
+    supported: bool,
+    /// Whether the server was last told to push updates.
+    enabled: bool,
+    /// Whether the screen still refreshes the last frame.
+    busy: bool,
+    asleep: bool,
+    /// The number of frames drawn so far.
+    frames: u64,
+    /// Fence requests waiting for the number of frames before them, with the flags and payload to answer.
+    fences: VecDeque<(u64, u32, Vec<u8>)>,
+}
+
+impl ContinuousUpdates {
+    pub fn new() -> ContinuousUpdates {
+        ContinuousUpdates::default()
+    }
+
+    /// Whether updates still have to be requested with FramebufferUpdateRequest.
+    pub fn polling(&self) -> bool {
+        !self.supported
+    }
+
+    /// Reacts to the extension messages of the server. The first EndOfContinuousUpdates
+    /// announces support, later ones only confirm a pause. Fence requests wait for `answer_fences`.
+    pub fn handle(&mut self, vnc: &mut Session, extension: Extension, rect: Rect) -> Result<(), Error> {
+        match extension {
+            Extension::EndOfContinuousUpdates if !self.supported => {
+                info!("server supports continuous updates");
+                self.supported = true;
+                self.sync(vnc, rect)
+            }
+            Extension::EndOfContinuousUpdates => {
+                debug!("continuous updates paused");
+                Ok(())
+            }
+            Extension::Fence { flags, payload, frames } if flags & FENCE_REQUEST != 0 => {
+                self.fences.push_back((frames, flags & FENCE_BLOCK_BEFORE, payload));
+                Ok(())
+            }
+            Extension::Fence { .. } | Extension::ExtendedDesktopSize { .. } => Ok(()),
+        }
+    }
+
+    /// Counts a frame as drawn, after its `Event::EndOfFrame` was handled.
+    pub fn frame_drawn(&mut self) {
+        self.frames += 1;
+    }
+
+    /// Answers the fence requests whose preceding frames are all drawn.
+    pub fn answer_fences(&mut self, vnc: &mut Session) -> Result<(), Error> {
+        while self.fences.front().is_some_and(|(frames, _, _)| *frames <= self.frames) {
+            let (_, flags, payload) = self.fences.pop_front().unwrap();
+            vnc.fence(flags, &payload)?;
+        }
+        Ok(())
+    }
+
+    /// Stops updates while the screen refreshes a frame.
+    pub fn pause(&mut self, vnc: &mut Session, rect: Rect) -> Result<(), Error> {
+        self.busy = true;
+        self.sync(vnc, rect)
+    }
+
+    /// Restarts updates once the screen is done refreshing.
+    pub fn resume(&mut self, vnc: &mut Session, rect: Rect) -> Result<(), Error> {
+        self.busy = false;
+        self.sync(vnc, rect)
+    }
+
+    /// Stops updates while the device sleeps, and restarts them on wake up.
+    pub fn sleep(&mut self, vnc: &mut Session, rect: Rect, asleep: bool) -> Result<(), Error> {
+        self.asleep = asleep;
+        self.sync(vnc, rect)
+    }
+
+    /// Moves updates to `rect` after the desktop was resized.
+    pub fn resize(&mut self, vnc: &mut Session, rect: Rect) -> Result<(), Error> {
+        if self.enabled {
+            vnc.enable_continuous_updates(true, rect)?;
+        }
+        Ok(())
+    }
+
+    /// Tells the server to push updates or not, if that changed.
+    fn sync(&mut self, vnc: &mut Session, rect: Rect) -> Result<(), Error> {
+        let enable = self.supported && !self.busy && !self.asleep;
+        if enable != self.enabled {
+            vnc.enable_continuous_updates(enable, rect)?;
+            self.enabled = enable;
+        }
+        Ok(())
+    }
+}
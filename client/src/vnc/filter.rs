@@ -0,0 +1,352 @@
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
+
+const FRAMEBUFFER_UPDATE: u8 = 0;
+const SET_COLOUR_MAP_ENTRIES: u8 = 1;
+const BELL: u8 = 2;
+const SERVER_CUT_TEXT: u8 = 3;
+const END_OF_CONTINUOUS_UPDATES: u8 = 150;
+const SERVER_FENCE: u8 = 248;
+
+const ENCODING_RAW: i32 = 0;
+const ENCODING_COPY_RECT: i32 = 1;
+const ENCODING_ZRLE: i32 = 16;
+const ENCODING_DESKTOP_SIZE: i32 = -223;
+const ENCODING_CURSOR: i32 = -239;
+const ENCODING_EXTENDED_DESKTOP_SIZE: i32 = -308;
+
+/// Server messages of the extensions rust-vnc doesn't know, taken out of the session by `Filter`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Extension {
+    EndOfContinuousUpdates,
+    /// A Fence, which came after the first `frames` FramebufferUpdates.
+    Fence { flags: u32, payload: Vec<u8>, frames: u64 },
+    /// An ExtendedDesktopSize rectangle: why the desktop has `width`x`height` now, and whether
+    /// a SetDesktopSize request of this client succeeded (`status` 0) or not.
+    ExtendedDesktopSize { reason: u16, status: u16, width: u16, height: u16 },
+}
+
+/// Sits between the server and `vnc::Client`, whose reader gives up on unknown message types.
+/// The handshake passes through unchanged; afterwards every server message is framed, and the
+/// ContinuousUpdates and Fence messages are handed out as `Extension`s instead of being forwarded.
+/// ExtendedDesktopSize rectangles are handed out as well; those that change the size reach
+/// rust-vnc as plain DesktopSize rectangles, so it still reports `Event::Resize`.
+/// Framing requires the size of all encodings in use, so only those of `connect` are supported;
+/// at anything else, the filter gives up and passes the rest of the session through unchanged.
+pub struct Filter {
+    framing: Arc<AtomicBool>,
+    bytes_per_pixel: Arc<AtomicU8>,
+    desktop_size: Arc<AtomicU32>,
+}
+
+impl Filter {
+    /// Relays `server` to the returned loopback stream, which replaces it for `vnc::Client`.
+    pub fn insert(server: TcpStream) -> io::Result<(Filter, TcpStream, Receiver<Extension>)> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let client = TcpStream::connect(listener.local_addr()?)?;
+        let (relay, _) = listener.accept()?;
+        client.set_nodelay(true)?;
+        relay.set_nodelay(true)?;
+
+        let filter = Filter {
+            framing: Arc::new(AtomicBool::new(false)),
+            bytes_per_pixel: Arc::new(AtomicU8::new(4)),
+            desktop_size: Arc::new(AtomicU32::new(0)),
+        };
+        let (tx, rx) = mpsc::channel();
+
+        let mut upstream = server.try_clone()?;
+        let mut downstream = relay.try_clone()?;
+        thread::spawn(move || {
+            io::copy(&mut downstream, &mut upstream).ok();
+            upstream.shutdown(Shutdown::Write).ok();
+        });
+
+        let framing = filter.framing.clone();
+        let bytes_per_pixel = filter.bytes_per_pixel.clone();
+        let desktop_size = filter.desktop_size.clone();
+        thread::spawn(move || {
+            let result = pass_through(&server, &relay, &framing)
+                .and_then(|leftover| {
+                    let reader = io::Cursor::new(leftover).chain(&server);
+                    let size = desktop_size.load(Ordering::SeqCst);
+                    frame(reader, &relay, &bytes_per_pixel, ((size >> 16) as u16, size as u16), &tx)
+                });
+            if let Err(error) = result {
+                warn!("server message filter stopped: {}", error);
+            }
+            relay.shutdown(Shutdown::Both).ok();
+            server.shutdown(Shutdown::Both).ok();
+        });
+
+        Ok((filter, client, rx))
+    }
+
+    /// Starts framing server messages, with pixels of `bits_per_pixel` on a desktop of `size`.
+    /// Must be called after the handshake, before any request that makes the server send further messages.
+    pub fn start(&self, bits_per_pixel: u8, size: (u16, u16)) {
+        self.bytes_per_pixel.store((bits_per_pixel / 8).max(1), Ordering::SeqCst);
+        self.desktop_size.store((size.0 as u32) << 16 | size.1 as u32, Ordering::SeqCst);
+        self.framing.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Forwards the handshake; returns the first bytes read once framing started.
+fn pass_through(mut server: &TcpStream, mut client: &TcpStream, framing: &AtomicBool) -> io::Result<Vec<u8>> {
+    let mut chunk = [0; 4096];
+    loop {
+        let len = server.read(&mut chunk)?;
+        if len == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if framing.load(Ordering::SeqCst) {
+            return Ok(chunk[..len].to_vec());
+        }
+        client.write_all(&chunk[..len])?;
+    }
+}
+
+/// Copies server messages from `reader` to `writer`, one at a time, apart from the extension messages.
+/// `size` is the desktop size at the start, which ExtendedDesktopSize rectangles are compared to.
+fn frame<R: Read, W: Write>(reader: R, writer: W, bytes_per_pixel: &AtomicU8, mut size: (u16, u16), extensions: &Sender<Extension>) -> io::Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+    let mut frames: u64 = 0;
+    loop {
+        let message_type = match reader.read_u8() {
+            Ok(message_type) => message_type,
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(error) => return Err(error),
+        };
+        match message_type {
+            FRAMEBUFFER_UPDATE => {
+                let mut header = [0; 3];
+                reader.read_exact(&mut header)?;
+                // the update is collected first, as dropping rectangles changes their number in the header
+                let mut rects = Vec::new();
+                let mut forwarded: u16 = 0;
+                let count = BigEndian::read_u16(&header[1..]);
+                for index in 0..count {
+                    let mut rect = [0; 12];
+                    reader.read_exact(&mut rect)?;
+                    let width = BigEndian::read_u16(&rect[4..]);
+                    let height = BigEndian::read_u16(&rect[6..]);
+                    let encoding = BigEndian::read_i32(&rect[8..]);
+                    if encoding == ENCODING_EXTENDED_DESKTOP_SIZE {
+                        let mut screens = [0; 4];
+                        reader.read_exact(&mut screens)?;
+                        copy(&mut reader, &mut io::sink(), 16 * screens[0] as u64)?;
+                        let reason = BigEndian::read_u16(&rect[0..]);
+                        let status = BigEndian::read_u16(&rect[2..]);
+                        extensions.send(Extension::ExtendedDesktopSize { reason, status, width, height }).ok();
+                        if status == 0 && (width, height) != size {
+                            size = (width, height);
+                            rects.extend_from_slice(&[0, 0, 0, 0]);
+                            rects.extend_from_slice(&rect[4..8]);
+                            rects.extend_from_slice(&ENCODING_DESKTOP_SIZE.to_be_bytes());
+                            forwarded += 1;
+                        }
+                        continue;
+                    }
+                    rects.extend_from_slice(&rect);
+                    forwarded += 1;
+                    let (width, height) = (width as u64, height as u64);
+                    let pixel = bytes_per_pixel.load(Ordering::SeqCst) as u64;
+                    let len = match encoding {
+                        ENCODING_RAW => width * height * pixel,
+                        ENCODING_COPY_RECT => 4,
+                        ENCODING_ZRLE => {
+                            let len = reader.read_u32::<BigEndian>()?;
+                            rects.extend_from_slice(&len.to_be_bytes());
+                            len as u64
+                        }
+                        ENCODING_DESKTOP_SIZE => {
+                            size = (width as u16, height as u16);
+                            0
+                        }
+                        ENCODING_CURSOR => width * height * pixel + width.div_ceil(8) * height,
+                        encoding => {
+                            warn!("can't frame encoding {}, no more extension messages", encoding);
+                            writer.write_all(&[message_type, header[0]])?;
+                            writer.write_all(&(forwarded + count - index - 1).to_be_bytes())?;
+                            writer.write_all(&rects)?;
+                            return pass_rest(reader, writer);
+                        }
+                    };
+                    copy(&mut reader, &mut rects, len)?;
+                }
+                writer.write_all(&[message_type, header[0]])?;
+                writer.write_all(&forwarded.to_be_bytes())?;
+                writer.write_all(&rects)?;
+                frames += 1;
+            }
+            SET_COLOUR_MAP_ENTRIES => {
+                let mut header = [0; 5];
+                reader.read_exact(&mut header)?;
+                writer.write_all(&[message_type])?;
+                writer.write_all(&header)?;
+                copy(&mut reader, &mut writer, 6 * BigEndian::read_u16(&header[3..]) as u64)?;
+            }
+            BELL => writer.write_all(&[message_type])?,
+            SERVER_CUT_TEXT => {
+                let mut header = [0; 7];
+                reader.read_exact(&mut header)?;
+                writer.write_all(&[message_type])?;
+                writer.write_all(&header)?;
+                copy(&mut reader, &mut writer, BigEndian::read_u32(&header[3..]) as u64)?;
+            }
+            END_OF_CONTINUOUS_UPDATES => {
+                extensions.send(Extension::EndOfContinuousUpdates).ok();
+            }
+            SERVER_FENCE => {
+                let mut padding = [0; 3];
+                reader.read_exact(&mut padding)?;
+                let flags = reader.read_u32::<BigEndian>()?;
+                let mut payload = vec![0; reader.read_u8()? as usize];
+                reader.read_exact(&mut payload)?;
+                extensions.send(Extension::Fence { flags, payload, frames }).ok();
+            }
+            message_type => {
+                warn!("can't frame server message {}, no more extension messages", message_type);
+                writer.write_all(&[message_type])?;
+                return pass_rest(reader, writer);
+            }
+        }
+        writer.flush()?;
+    }
+}
+
+/// Copies the rest of the session as it is, once a message couldn't be framed.
+/// rust-vnc then gets to handle the message itself, as it would without the filter.
+fn pass_rest<R: Read, W: Write>(mut reader: R, writer: BufWriter<W>) -> io::Result<()> {
+    let mut writer = writer.into_inner().map_err(|error| error.into_error())?;
+    io::copy(&mut reader, &mut writer)?;
+    Ok(())
+}
+
+fn copy<R: Read, W: Write>(reader: &mut R, writer: &mut W, len: u64) -> io::Result<()> {
+    let copied = io::copy(&mut reader.take(len), writer)?;
+    if copied < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(width: u16, height: u16, encoding: i32) -> Vec<u8> {
+        let mut rect = vec![0, 1, 0, 2];
+        rect.extend_from_slice(&width.to_be_bytes());
+        rect.extend_from_slice(&height.to_be_bytes());
+        rect.extend_from_slice(&encoding.to_be_bytes());
+        rect
+    }
+
+    #[test]
+    fn frames_messages() {
+        let mut update = vec![FRAMEBUFFER_UPDATE, 0, 0, 5];
+        update.extend(rect(2, 1, ENCODING_RAW));
+        update.extend([1, 2, 3, 4, 5, 6, 7, 8]);
+        update.extend(rect(3, 3, ENCODING_COPY_RECT));
+        update.extend([0, 0, 0, 0]);
+        update.extend(rect(8, 8, ENCODING_ZRLE));
+        update.extend([0, 0, 0, 3, 0xaa, 0xbb, 0xcc]);
+        update.extend(rect(640, 480, ENCODING_DESKTOP_SIZE));
+        update.extend(rect(9, 2, ENCODING_CURSOR));
+        update.extend([0x55; 9 * 2 * 4 + 2 * 2]);
+        let colour_map = vec![SET_COLOUR_MAP_ENTRIES, 0, 0, 0, 0, 1, 1, 2, 3, 4, 5, 6];
+        let cut_text = vec![SERVER_CUT_TEXT, 0, 0, 0, 0, 0, 0, 2, b'h', b'i'];
+        let fence = vec![SERVER_FENCE, 0, 0, 0, 0x80, 0, 0, 3, 2, 0xf0, 0x0d];
+
+        let mut input = update.clone();
+        input.push(END_OF_CONTINUOUS_UPDATES);
+        input.extend(&colour_map);
+        input.extend(&fence);
+        input.push(BELL);
+        input.extend(&cut_text);
+
+        let (tx, rx) = mpsc::channel();
+        let mut output = Vec::new();
+        frame(&input[..], &mut output, &AtomicU8::new(4), (640, 480), &tx).unwrap();
+
+        let mut expected = update;
+        expected.extend(colour_map);
+        expected.push(BELL);
+        expected.extend(cut_text);
+        assert_eq!(output, expected, "everything but the extension messages passed on");
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![
+            Extension::EndOfContinuousUpdates,
+            Extension::Fence { flags: 0x80000003, payload: vec![0xf0, 0x0d], frames: 1 },
+        ]);
+    }
+
+    #[test]
+    fn turns_extended_desktop_size_into_desktop_size() {
+        let extended = |reason: u16, status: u16, width: u16, height: u16| {
+            let mut rect = [reason.to_be_bytes(), status.to_be_bytes(), width.to_be_bytes(), height.to_be_bytes()].concat();
+            rect.extend(ENCODING_EXTENDED_DESKTOP_SIZE.to_be_bytes());
+            rect.extend([1, 0, 0, 0]);
+            rect.extend([0; 16]);
+            rect
+        };
+        let mut input = vec![FRAMEBUFFER_UPDATE, 0, 0, 2];
+        input.extend(extended(0, 0, 640, 480)); // the announcement, at the current size
+        input.extend(rect(1, 1, ENCODING_COPY_RECT));
+        input.extend([0, 0, 0, 0]);
+        input.extend([FRAMEBUFFER_UPDATE, 0, 0, 1]);
+        input.extend(extended(1, 3, 640, 480)); // a refused request
+        input.extend([FRAMEBUFFER_UPDATE, 0, 0, 1]);
+        input.extend(extended(1, 0, 1404, 1872));
+
+        let (tx, rx) = mpsc::channel();
+        let mut output = Vec::new();
+        frame(&input[..], &mut output, &AtomicU8::new(4), (640, 480), &tx).unwrap();
+
+        let mut expected = vec![FRAMEBUFFER_UPDATE, 0, 0, 1];
+        expected.extend(rect(1, 1, ENCODING_COPY_RECT));
+        expected.extend([0, 0, 0, 0]);
+        expected.extend([FRAMEBUFFER_UPDATE, 0, 0, 0]);
+        expected.extend([FRAMEBUFFER_UPDATE, 0, 0, 1, 0, 0, 0, 0]);
+        expected.extend(rect(1404, 1872, ENCODING_DESKTOP_SIZE)[4..].iter());
+        assert_eq!(output, expected, "only the size change reaches rust-vnc");
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![
+            Extension::ExtendedDesktopSize { reason: 0, status: 0, width: 640, height: 480 },
+            Extension::ExtendedDesktopSize { reason: 1, status: 3, width: 640, height: 480 },
+            Extension::ExtendedDesktopSize { reason: 1, status: 0, width: 1404, height: 1872 },
+        ]);
+    }
+
+    #[test]
+    fn passes_unknown_encodings_through() {
+        let mut announcement = rect(640, 480, ENCODING_EXTENDED_DESKTOP_SIZE);
+        announcement.extend([0; 4]);
+        let mut rest = rect(1, 1, 5); // Hextile
+        rest.extend([1, 0xaa]);
+        rest.extend(rect(1, 1, ENCODING_COPY_RECT));
+        rest.extend([0, 0, 0, 0, SERVER_FENCE, 0, 0, 0, 0x80, 0, 0, 0, 0]);
+        let mut input = vec![FRAMEBUFFER_UPDATE, 0, 0, 3];
+        input.extend(announcement);
+        input.extend(&rest);
+
+        let (tx, rx) = mpsc::channel();
+        let mut output = Vec::new();
+        frame(&input[..], &mut output, &AtomicU8::new(4), (640, 480), &tx).unwrap();
+
+        let mut expected = vec![FRAMEBUFFER_UPDATE, 0, 0, 2];
+        expected.extend(rest);
+        assert_eq!(output, expected, "the rest reaches rust-vnc unchanged");
+        assert_eq!(rx.try_iter().count(), 1, "only the extension messages before");
+
+        let mut output = Vec::new();
+        frame(&[BELL, 99, 1, 2][..], &mut output, &AtomicU8::new(4), (640, 480), &tx).unwrap();
+        assert_eq!(output, vec![BELL, 99, 1, 2]);
+    }
+}
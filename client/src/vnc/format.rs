@@ -0,0 +1,64 @@
+use std::str::FromStr;
+
+use vnc::PixelFormat;
+
+/// The pixel format to ask the server for. The 8 bit formats move the color quantisation
+/// to the server, and compress a lot better with ZRLE.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorFormat {
+    /// Keep the format the server announced.
+    Native,
+    /// 4 bit true colour, with all channels in the same bits: the server sends the 16 gray levels
+    /// of the e-ink panel, and colours as the brightest of their channels, roughly.
+    Gray16,
+    /// 8 bit true colour, with 3 bits red and green and 2 bits blue, like `vncviewer -bgr233`.
+    Bgr233,
+}
+
+impl ColorFormat {
+    pub const NAMES: [&'static str; 3] = ["native", "gray16", "bgr233"];
+
+    /// The format for SetPixelFormat, `None` to keep the server's.
+    pub fn pixel_format(&self) -> Option<PixelFormat> {
+        match self {
+            ColorFormat::Native => None,
+            ColorFormat::Gray16 => Some(PixelFormat {
+                bits_per_pixel: 8,
+                depth: 4,
+                big_endian: false,
+                true_colour: true,
+                red_max: 15,
+                green_max: 15,
+                blue_max: 15,
+                red_shift: 0,
+                green_shift: 0,
+                blue_shift: 0,
+            }),
+            ColorFormat::Bgr233 => Some(PixelFormat {
+                bits_per_pixel: 8,
+                depth: 8,
+                big_endian: false,
+                true_colour: true,
+                red_max: 7,
+                green_max: 7,
+                blue_max: 3,
+                red_shift: 0,
+                green_shift: 3,
+                blue_shift: 6,
+            }),
+        }
+    }
+}
+
+impl FromStr for ColorFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<ColorFormat, String> {
+        match name {
+            "native" => Ok(ColorFormat::Native),
+            "gray16" => Ok(ColorFormat::Gray16),
+            "bgr233" => Ok(ColorFormat::Bgr233),
+            _ => Err(format!("unknown pixel format '{}', expected one of {:?}", name, ColorFormat::NAMES)),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::net::{Ipv4Addr, TcpListener};
+
+use nix::ifaddrs::getifaddrs;
+use nix::net::if_::InterfaceFlags;
+
+use crate::error::Error;
+use crate::vnc::connect::handshake;
+use crate::vnc::{Connection, Session};
+
+/// The port `vncviewer -listen` uses, and `x11vnc -connect` expects by default.
+pub const DEFAULT_LISTEN_PORT: u16 = 5500;
+
+/// Reverse connection: waits on `port` until the VNC server connects to us, then runs the usual handshake.
+/// `on_waiting` is called once the socket is bound, with the addresses the server may connect to.
+/// Servers that drop out during the handshake are logged, and the next connection is awaited.
+pub fn listen<F>(con: Connection, port: u16, on_waiting: F) -> Result<Session, Error>
+where
+    F: FnOnce(&[Ipv4Addr], u16),
+{
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).map_err(Error::Connect)?;
+    let port = listener.local_addr().map_err(Error::Connect)?.port();
+    info!("listening for a VNC server on port {}", port);
+    on_waiting(&local_addresses(), port);
+
+    loop {
+        let (stream, peer) = listener.accept().map_err(Error::Connect)?;
+        info!("server connected from {}", peer);
+        match handshake(stream, con) {
+            Ok(vnc) => return Ok(vnc),
+            Err(error) if error.is_transient() => {
+                warn!("handshake with {} failed: {}; waiting for the next server", peer, error);
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// IPv4 addresses of all network interfaces that are up, except the loopback.
+pub fn local_addresses() -> Vec<Ipv4Addr> {
+    let interfaces = match getifaddrs() {
+        Ok(interfaces) => interfaces,
+        Err(error) => {
+            warn!("cannot list network interfaces: {}", error);
+            return Vec::new();
+        }
+    };
+    interfaces
+        .filter(|interface| {
+            interface.flags.contains(InterfaceFlags::IFF_UP)
+                && !interface.flags.contains(InterfaceFlags::IFF_LOOPBACK)
+        })
+        .filter_map(|interface| interface.address)
+        .filter_map(|address| address.as_sockaddr_in().map(|address| Ipv4Addr::from(address.ip())))
+        .collect()
+}
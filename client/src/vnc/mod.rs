@@ -1,7 +1,21 @@
 #![allow(unused)]
 
 mod connect;
+mod continuous;
+mod filter;
+mod format;
 mod auth;
+mod listen;
+mod reconnect;
+mod session;
+#[cfg(feature = "tls")]
+mod vencrypt;
 
 pub use self::connect::connect;
 pub use self::connect::Connection;
+pub use self::continuous::ContinuousUpdates;
+pub use self::filter::Extension;
+pub use self::format::ColorFormat;
+pub use self::listen::{listen, DEFAULT_LISTEN_PORT};
+pub use self::reconnect::{connect_with_backoff, Backoff};
+pub use self::session::Session;
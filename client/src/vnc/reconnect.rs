@@ -0,0 +1,92 @@
+use std::thread;
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::vnc::{connect, Connection, Session};
+
+const INITIAL_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Exponential backoff between two connection attempts.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    delay: Duration,
+    attempts: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff {
+            initial,
+            max,
+            delay: initial,
+            attempts: 0,
+        }
+    }
+
+    /// The delay to wait before the next attempt; doubles on every call up to `max`.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(self.max);
+        self.attempts += 1;
+        delay
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn reset(&mut self) {
+        self.delay = self.initial;
+        self.attempts = 0;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::new(INITIAL_DELAY, MAX_DELAY)
+    }
+}
+
+/// Connects to the server, retrying until a session is established.
+/// `on_failure` is called with the error and the attempt number before each wait.
+/// Errors that won't go away by retrying, like rejected credentials, are returned immediately.
+pub fn connect_with_backoff<F>(con: Connection, backoff: &mut Backoff, mut on_failure: F) -> Result<Session, Error>
+where
+    F: FnMut(&Error, u32),
+{
+    loop {
+        match connect(con) {
+            Ok(vnc) => {
+                backoff.reset();
+                return Ok(vnc);
+            }
+            Err(error) if !error.is_transient() => return Err(error),
+            Err(error) => {
+                let delay = backoff.next_delay();
+                warn!("{}; retrying in {}s", error, delay.as_secs_f32());
+                on_failure(&error, backoff.attempts());
+                thread::sleep(delay);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exponential_delays() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(10));
+        let delays: Vec<u64> = (0..6).map(|_| backoff.next_delay().as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 10, 10], "doubles until capped");
+        assert_eq!(backoff.attempts(), 6);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay().as_secs(), 1, "starts over after reset");
+        assert_eq!(backoff.attempts(), 1);
+    }
+}
@@ -0,0 +1,194 @@
+use std::io::Write;
+use std::net::{Shutdown, TcpStream};
+use std::ops::{Deref, DerefMut};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use byteorder::{BigEndian, WriteBytesExt};
+use vnc::{Client, Rect};
+
+use crate::error::Error;
+use crate::vnc::filter::Extension;
+
+const ENABLE_CONTINUOUS_UPDATES: u8 = 150;
+const CLIENT_FENCE: u8 = 248;
+const SET_DESKTOP_SIZE: u8 = 251;
+
+/// ExtendedDesktopSize reason for a change this client requested.
+const REASON_CLIENT: u16 = 1;
+/// How long a SetDesktopSize request waits for the answer of the server, before it's given up.
+const RESIZE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An established VNC session. Derefs to the `vnc::Client`, and keeps a clone of its socket
+/// for the client to server messages that rust-vnc has no API for.
+pub struct Session {
+    client: Client,
+    stream: TcpStream,
+    /// Extension messages taken out by a `Filter`, if one was inserted.
+    extensions: Option<Receiver<Extension>>,
+    /// Whether the server announced ExtendedDesktopSize, which SetDesktopSize requires.
+    extended_desktop_size: bool,
+    /// The desktop size asked for, until the server answered.
+    requested_size: Option<(u16, u16)>,
+    /// When the request was sent.
+    requested_at: Option<Instant>,
+}
+
+impl Session {
+    pub(crate) fn new(client: Client, stream: TcpStream, extensions: Option<Receiver<Extension>>) -> Session {
+        Session { client, stream, extensions, extended_desktop_size: false, requested_size: None, requested_at: None }
+    }
+
+    /// The next extension message of the server, if any arrived.
+    pub fn poll_extension(&mut self) -> Option<Extension> {
+        self.extensions.as_ref()?.try_recv().ok()
+    }
+
+    /// Starts or stops the server pushing updates of `rect` (EnableContinuousUpdates).
+    /// Only valid after the server announced the extension with EndOfContinuousUpdates.
+    pub fn enable_continuous_updates(&mut self, enable: bool, rect: Rect) -> Result<(), Error> {
+        self.send(&enable_continuous_updates(enable, rect))
+    }
+
+    /// Sends a Fence message, e.g. answering a request of the server.
+    pub fn fence(&mut self, flags: u32, payload: &[u8]) -> Result<(), Error> {
+        self.send(&fence(flags, payload))
+    }
+
+    /// Asks the server to resize the desktop to a single screen of `width`x`height` (SetDesktopSize).
+    /// The request is held back until the server announced ExtendedDesktopSize, and never sent to
+    /// servers without it. A successful change arrives as `Event::Resize`.
+    pub fn set_desktop_size(&mut self, width: u16, height: u16) -> Result<(), Error> {
+        self.requested_size = Some((width, height));
+        if self.extended_desktop_size {
+            self.request_size(width, height)?;
+        }
+        Ok(())
+    }
+
+    /// Whether a sent SetDesktopSize request still awaits the answer of the server.
+    /// A request without an answer after `RESIZE_TIMEOUT` is given up.
+    pub fn resizing(&mut self) -> bool {
+        let Some(requested_at) = self.requested_at else { return false };
+        if requested_at.elapsed() < RESIZE_TIMEOUT {
+            return true;
+        }
+        if let Some((width, height)) = self.requested_size.take() {
+            warn!("server didn't answer the request to resize the desktop to {}x{}", width, height);
+        }
+        self.requested_at = None;
+        false
+    }
+
+    /// Reacts to an `Extension::ExtendedDesktopSize`: the first one announces the extension and
+    /// sends a held back request, a later one of `REASON_CLIENT` answers it. After a refusal the
+    /// desktop keeps its size.
+    pub fn handle_desktop_size(&mut self, reason: u16, status: u16) -> Result<(), Error> {
+        if !self.extended_desktop_size {
+            info!("server supports resizing the desktop");
+            self.extended_desktop_size = true;
+            if let Some((width, height)) = self.requested_size {
+                self.request_size(width, height)?;
+            }
+        } else if reason == REASON_CLIENT {
+            self.requested_at = None;
+            if let Some((width, height)) = self.requested_size.take() {
+                if status != 0 {
+                    warn!("server refused to resize the desktop to {}x{}: {}", width, height, resize_status(status));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn request_size(&mut self, width: u16, height: u16) -> Result<(), Error> {
+        self.send(&set_desktop_size(width, height))?;
+        self.requested_at = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Closes the connection; the session then reports `Event::Disconnected`.
+    pub fn disconnect(&mut self) {
+        self.stream.shutdown(Shutdown::Both).ok();
+    }
+
+    fn send(&mut self, message: &[u8]) -> Result<(), Error> {
+        self.stream
+            .write_all(message)
+            .map_err(|error| Error::Protocol(vnc::Error::Io(error)))
+    }
+}
+
+fn enable_continuous_updates(enable: bool, rect: Rect) -> Vec<u8> {
+    let mut message = vec![ENABLE_CONTINUOUS_UPDATES, enable as u8];
+    message.write_u16::<BigEndian>(rect.left).unwrap();
+    message.write_u16::<BigEndian>(rect.top).unwrap();
+    message.write_u16::<BigEndian>(rect.width).unwrap();
+    message.write_u16::<BigEndian>(rect.height).unwrap();
+    message
+}
+
+fn fence(flags: u32, payload: &[u8]) -> Vec<u8> {
+    let mut message = vec![CLIENT_FENCE, 0, 0, 0];
+    message.write_u32::<BigEndian>(flags).unwrap();
+    message.push(payload.len() as u8);
+    message.extend_from_slice(payload);
+    message
+}
+
+fn resize_status(status: u16) -> &'static str {
+    match status {
+        1 => "prohibited",
+        2 => "out of resources",
+        3 => "invalid screen layout",
+        _ => "unknown error",
+    }
+}
+
+fn set_desktop_size(width: u16, height: u16) -> Vec<u8> {
+    let mut message = vec![SET_DESKTOP_SIZE, 0];
+    message.write_u16::<BigEndian>(width).unwrap();
+    message.write_u16::<BigEndian>(height).unwrap();
+    message.extend_from_slice(&[1, 0]); // number of screens, padding
+    message.write_u32::<BigEndian>(0).unwrap(); // screen id
+    message.write_u16::<BigEndian>(0).unwrap(); // x
+    message.write_u16::<BigEndian>(0).unwrap(); // y
+    message.write_u16::<BigEndian>(width).unwrap();
+    message.write_u16::<BigEndian>(height).unwrap();
+    message.write_u32::<BigEndian>(0).unwrap(); // flags
+    message
+}
+
+impl Deref for Session {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        &self.client
+    }
+}
+
+impl DerefMut for Session {
+    fn deref_mut(&mut self) -> &mut Client {
+        &mut self.client
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_desktop_size_message() {
+        assert_eq!(set_desktop_size(1404, 1872), vec![
+            251, 0, 0x05, 0x7c, 0x07, 0x50, 1, 0,
+            0, 0, 0, 0, 0, 0, 0, 0, 0x05, 0x7c, 0x07, 0x50, 0, 0, 0, 0,
+        ]);
+    }
+
+    #[test]
+    fn continuous_updates_messages() {
+        let rect = Rect { left: 0, top: 0, width: 1404, height: 1872 };
+        assert_eq!(enable_continuous_updates(true, rect), vec![150, 1, 0, 0, 0, 0, 0x05, 0x7c, 0x07, 0x50]);
+        assert_eq!(fence(3, &[0xf0, 0x0d]), vec![248, 0, 0, 0, 0, 0, 0, 3, 2, 0xf0, 0x0d]);
+    }
+}
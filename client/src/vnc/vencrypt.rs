@@ -0,0 +1,437 @@
+//! VeNCrypt security (RFB security type 19), as offered by TigerVNC.
+//!
+//! The `vnc` crate only knows the classic security types. So the VeNCrypt handshake and the
+//! TLS session are handled here, and the decrypted session is relayed to the `vnc` client
+//! over a loopback socket, on which we pose as a server that requires no authentication.
+
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, TcpListener, TcpStream};
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use openssl::hash::MessageDigest;
+use openssl::ssl::{SslConnector, SslMethod, SslStream, SslVerifyMode, SslVersion};
+use openssl::x509::X509StoreContextRef;
+
+use crate::error::Error;
+use crate::vnc::Connection;
+
+const PROTOCOL_VERSION: &[u8; 12] = b"RFB 003.008\n";
+/// The oldest version with VeNCrypt; we answer with it to servers that announce no newer one.
+const PROTOCOL_VERSION_3_7: &[u8; 12] = b"RFB 003.007\n";
+const SECURITY_NONE: u8 = 1;
+const SECURITY_VENCRYPT: u8 = 19;
+const VENCRYPT_VERSION: (u8, u8) = (0, 2);
+const RELAY_POLL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SubType {
+    TlsNone = 257,
+    TlsPlain = 259,
+    X509None = 260,
+    X509Plain = 262,
+}
+
+impl SubType {
+    fn is_x509(self) -> bool {
+        matches!(self, SubType::X509None | SubType::X509Plain)
+    }
+
+    fn is_plain(self) -> bool {
+        matches!(self, SubType::TlsPlain | SubType::X509Plain)
+    }
+}
+
+/// The sub types we are willing to use, most preferred first.
+/// A configured CA or fingerprint demands a verified certificate, so anonymous TLS is ruled out;
+/// without them there is nothing to verify a certificate against.
+pub fn acceptable_sub_types(con: &Connection) -> Vec<SubType> {
+    let verified = con.tls_ca.is_some() || con.tls_fingerprint.is_some();
+    let plain = con.username.is_some() && con.password.is_some();
+    match (verified, plain) {
+        (true, true) => vec![SubType::X509Plain, SubType::X509None],
+        (true, false) => vec![SubType::X509None],
+        (false, true) => vec![SubType::TlsPlain, SubType::TlsNone],
+        (false, false) => vec![SubType::TlsNone],
+    }
+}
+
+/// Runs the VeNCrypt handshake on `stream` and returns a loopback stream,
+/// which carries the remaining, decrypted session for `vnc::Client`.
+pub fn secure(mut stream: TcpStream, con: &Connection) -> Result<TcpStream, Error> {
+    let mut version = [0; 12];
+    stream.read_exact(&mut version).map_err(protocol_error)?;
+    if version[..] < PROTOCOL_VERSION_3_7[..] {
+        return Err(unexpected("VeNCrypt requires RFB 3.7 or newer"));
+    }
+    // never a newer version than the server's
+    let protocol = if version[..] < PROTOCOL_VERSION[..] { PROTOCOL_VERSION_3_7 } else { PROTOCOL_VERSION };
+    stream.write_all(protocol).map_err(protocol_error)?;
+
+    let count = stream.read_u8().map_err(protocol_error)?;
+    if count == 0 {
+        return Err(Error::Protocol(vnc::Error::Server(read_reason(&mut stream)?)));
+    }
+    let mut security_types = vec![0; count as usize];
+    stream.read_exact(&mut security_types).map_err(protocol_error)?;
+    if !security_types.contains(&SECURITY_VENCRYPT) {
+        return Err(Error::Auth(format!("server does not offer VeNCrypt, only security types {:?}", security_types)));
+    }
+    stream.write_u8(SECURITY_VENCRYPT).map_err(protocol_error)?;
+
+    let server_version = (stream.read_u8().map_err(protocol_error)?, stream.read_u8().map_err(protocol_error)?);
+    if server_version < VENCRYPT_VERSION {
+        return Err(unexpected("VeNCrypt version 0.2 not supported by server"));
+    }
+    stream.write_all(&[VENCRYPT_VERSION.0, VENCRYPT_VERSION.1]).map_err(protocol_error)?;
+    if stream.read_u8().map_err(protocol_error)? != 0 {
+        return Err(unexpected("VeNCrypt version 0.2 rejected by server"));
+    }
+
+    let count = stream.read_u8().map_err(protocol_error)?;
+    let mut offered = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        offered.push(stream.read_u32::<BigEndian>().map_err(protocol_error)?);
+    }
+    debug!("offered VeNCrypt sub types: {:?}", offered);
+    let sub_type = acceptable_sub_types(con).into_iter()
+        .find(|sub_type| offered.contains(&(*sub_type as u32)))
+        .ok_or_else(|| Error::Auth(format!("none of the offered VeNCrypt sub types {:?} is acceptable", offered)))?;
+    info!("using VeNCrypt {:?}", sub_type);
+    stream.write_u32::<BigEndian>(sub_type as u32).map_err(protocol_error)?;
+    if stream.read_u8().map_err(protocol_error)? != 1 {
+        return Err(unexpected("VeNCrypt sub type rejected by server"));
+    }
+
+    let mut tls = handshake(stream, sub_type, con)?;
+
+    if sub_type.is_plain() {
+        let username = con.username.unwrap_or_default();
+        let password = con.password.unwrap_or_default();
+        tls.write_u32::<BigEndian>(username.len() as u32).map_err(protocol_error)?;
+        tls.write_u32::<BigEndian>(password.len() as u32).map_err(protocol_error)?;
+        tls.write_all(username.as_bytes()).map_err(protocol_error)?;
+        tls.write_all(password.as_bytes()).map_err(protocol_error)?;
+    }
+
+    if tls.read_u32::<BigEndian>().map_err(protocol_error)? != 0 {
+        // only 3.8 explains a failed SecurityResult
+        let reason = if protocol == PROTOCOL_VERSION { read_reason(&mut tls).ok() } else { None };
+        return Err(Error::Auth(reason.unwrap_or_else(|| "rejected by server".to_string())));
+    }
+
+    relay(tls, protocol).map_err(Error::Connect)
+}
+
+fn handshake(stream: TcpStream, sub_type: SubType, con: &Connection) -> Result<SslStream<TcpStream>, Error> {
+    let mut builder = SslConnector::builder(SslMethod::tls_client()).map_err(tls_error)?;
+    if sub_type.is_x509() {
+        if let Some(ca) = con.tls_ca {
+            builder.set_ca_file(ca).map_err(tls_error)?;
+        }
+        if let Some(fingerprint) = con.tls_fingerprint.map(normalize_fingerprint) {
+            builder.set_verify_callback(SslVerifyMode::PEER, move |_, ctx| pinned(ctx, &fingerprint));
+        }
+    } else {
+        // Anonymous Diffie-Hellman; only available up to TLS 1.2 and at security level 0.
+        builder.set_max_proto_version(Some(SslVersion::TLS1_2)).map_err(tls_error)?;
+        builder.set_cipher_list("aNULL:!eNULL:@SECLEVEL=0").map_err(tls_error)?;
+        builder.set_verify(SslVerifyMode::NONE);
+    }
+
+    let mut config = builder.build().configure().map_err(tls_error)?;
+    if !sub_type.is_x509() || con.tls_fingerprint.is_some() {
+        config = config.verify_hostname(false).use_server_name_indication(false);
+    }
+    config.connect(con.host, stream)
+        .map_err(|error| Error::Auth(format!("TLS handshake failed: {}", error)))
+}
+
+/// Accepts the presented certificate chain, if the server certificate has the pinned SHA-256 fingerprint.
+fn pinned(ctx: &mut X509StoreContextRef, fingerprint: &str) -> bool {
+    if ctx.error_depth() > 0 {
+        return true; // the chain is vouched for by the pinned leaf
+    }
+    match ctx.current_cert().map(|cert| cert.digest(MessageDigest::sha256())) {
+        Some(Ok(digest)) => {
+            let actual: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+            if actual != fingerprint {
+                error!("server certificate fingerprint {} does not match the pinned {}", actual, fingerprint);
+            }
+            actual == fingerprint
+        }
+        _ => false,
+    }
+}
+
+/// Lower case hex digits; drops the colons of the `openssl x509 -fingerprint` notation.
+pub fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint.chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Connects a loopback socket pair: one end is returned, on the other end we complete the handshake
+/// of a server that needs no authentication, speaking the `protocol` version agreed with the real
+/// server, then pass all traffic through the TLS session.
+fn relay(tls: SslStream<TcpStream>, protocol: &'static [u8; 12]) -> io::Result<TcpStream> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let client = TcpStream::connect(listener.local_addr()?)?;
+    let (mut local, _) = listener.accept()?;
+    tls.get_ref().set_read_timeout(Some(RELAY_POLL))?;
+    let tls = Arc::new(Mutex::new(tls));
+
+    thread::spawn(move || {
+        if let Err(error) = serve_none_auth(&mut local, protocol) {
+            error!("loopback handshake failed: {}", error);
+            return;
+        }
+        let upstream = Arc::clone(&tls);
+        let mut local_rx = match local.try_clone() {
+            Ok(stream) => stream,
+            Err(_) => return,
+        };
+        thread::spawn(move || {
+            let mut buf = [0; 16 * 1024];
+            while let Ok(n) = local_rx.read(&mut buf) {
+                if n == 0 || upstream.lock().unwrap().write_all(&buf[..n]).is_err() {
+                    break;
+                }
+            }
+            upstream.lock().unwrap().shutdown().ok();
+        });
+
+        let fd = tls.lock().unwrap().get_ref().as_raw_fd();
+        let mut buf = [0; 64 * 1024];
+        let mut pending = false;
+        loop {
+            if !pending && !readable(fd, RELAY_POLL) {
+                continue;
+            }
+            let mut session = tls.lock().unwrap();
+            let n = match session.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+                    pending = false;
+                    continue;
+                }
+                Err(e) => {
+                    error!("TLS session failed: {}", e);
+                    break;
+                }
+            };
+            pending = session.ssl().pending() > 0;
+            drop(session);
+            if local.write_all(&buf[..n]).is_err() {
+                break;
+            }
+        }
+        local.shutdown(Shutdown::Both).ok();
+    });
+
+    Ok(client)
+}
+
+/// RFB 3.7 has no SecurityResult for the None security type, 3.8 has.
+fn serve_none_auth(local: &mut TcpStream, protocol: &[u8; 12]) -> io::Result<()> {
+    local.write_all(protocol)?;
+    let mut version = [0; 12];
+    local.read_exact(&mut version)?;
+    local.write_all(&[1, SECURITY_NONE])?;
+    local.read_u8()?;
+    if protocol == PROTOCOL_VERSION {
+        local.write_u32::<BigEndian>(0)?;
+    }
+    Ok(())
+}
+
+fn readable(fd: i32, timeout: Duration) -> bool {
+    let mut pfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let ret = unsafe { libc::poll(&mut pfd as *mut libc::pollfd, 1, timeout.as_millis() as libc::c_int) };
+    ret > 0
+}
+
+fn read_reason<R: Read>(stream: &mut R) -> Result<String, Error> {
+    let len = stream.read_u32::<BigEndian>().map_err(protocol_error)?;
+    let mut reason = vec![0; len as usize];
+    stream.read_exact(&mut reason).map_err(protocol_error)?;
+    Ok(String::from_utf8_lossy(&reason).into_owned())
+}
+
+fn protocol_error(error: io::Error) -> Error {
+    Error::Protocol(vnc::Error::Io(error))
+}
+
+fn unexpected(message: &'static str) -> Error {
+    Error::Protocol(vnc::Error::Unexpected(message))
+}
+
+fn tls_error(error: openssl::error::ErrorStack) -> Error {
+    Error::Auth(format!("TLS setup failed: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::ssl::SslAcceptor;
+    use openssl::x509::{X509Name, X509};
+
+    fn connection<'a>(fingerprint: Option<&'a str>) -> Connection<'a> {
+        Connection {
+            host: "127.0.0.1",
+            port: 0,
+            username: Some("kobo"),
+            password: Some("a password longer than 8 bytes"),
+            exclusive: false,
+            tls: true,
+            tls_ca: None,
+            tls_fingerprint: fingerprint,
+            format: crate::vnc::ColorFormat::Native,
+            local_cursor: false,
+            continuous_updates: false,
+            resize: false,
+        }
+    }
+
+    /// A stand-in for a TigerVNC server announcing `protocol`, offering a single VeNCrypt sub type.
+    fn serve(acceptor: SslAcceptor, sub_type: SubType, protocol: &'static [u8; 12]) -> (u16, thread::JoinHandle<Option<(String, String)>>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(protocol).unwrap();
+            let mut version = [0; 12];
+            stream.read_exact(&mut version).unwrap();
+            assert_eq!(&version, protocol, "the client answers with the version of the server");
+            stream.write_all(&[1, SECURITY_VENCRYPT]).unwrap();
+            assert_eq!(stream.read_u8().unwrap(), SECURITY_VENCRYPT);
+            stream.write_all(&[0, 2]).unwrap();
+            assert_eq!((stream.read_u8().unwrap(), stream.read_u8().unwrap()), (0, 2));
+            stream.write_all(&[0, 1]).unwrap();
+            stream.write_u32::<BigEndian>(sub_type as u32).unwrap();
+            assert_eq!(stream.read_u32::<BigEndian>().unwrap(), sub_type as u32);
+            stream.write_u8(1).unwrap();
+
+            let mut tls = acceptor.accept(stream).ok()?;
+            let mut credentials = (String::new(), String::new());
+            if sub_type.is_plain() {
+                let user_len = tls.read_u32::<BigEndian>().unwrap() as usize;
+                let pass_len = tls.read_u32::<BigEndian>().unwrap() as usize;
+                let mut user = vec![0; user_len];
+                let mut pass = vec![0; pass_len];
+                tls.read_exact(&mut user).unwrap();
+                tls.read_exact(&mut pass).unwrap();
+                credentials = (String::from_utf8(user).unwrap(), String::from_utf8(pass).unwrap());
+            }
+            tls.write_u32::<BigEndian>(0).unwrap();
+
+            // echo the ClientInit message
+            let shared = tls.read_u8().unwrap();
+            tls.write_u8(shared).unwrap();
+            Some(credentials)
+        });
+        (port, server)
+    }
+
+    /// Plays the part of `vnc::Client` on the loopback end.
+    fn client_handshake(local: &mut TcpStream, protocol: &[u8; 12]) {
+        let mut version = [0; 12];
+        local.read_exact(&mut version).unwrap();
+        assert_eq!(&version, protocol);
+        local.write_all(protocol).unwrap();
+        assert_eq!((local.read_u8().unwrap(), local.read_u8().unwrap()), (1, SECURITY_NONE));
+        local.write_u8(SECURITY_NONE).unwrap();
+        if protocol == PROTOCOL_VERSION {
+            assert_eq!(local.read_u32::<BigEndian>().unwrap(), 0);
+        }
+    }
+
+    fn self_signed() -> (openssl::pkey::PKey<openssl::pkey::Private>, X509) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509Name::builder().unwrap();
+        name.append_entry_by_text("CN", "vncserver").unwrap();
+        let name = name.build();
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+        (key, cert.build())
+    }
+
+    #[test]
+    fn tls_plain_relays_session() {
+        for protocol in [PROTOCOL_VERSION, PROTOCOL_VERSION_3_7] {
+            let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+            acceptor.set_max_proto_version(Some(SslVersion::TLS1_2)).unwrap();
+            acceptor.set_cipher_list("aNULL:!eNULL:@SECLEVEL=0").unwrap();
+            let (port, server) = serve(acceptor.build(), SubType::TlsPlain, protocol);
+
+            let con = connection(None);
+            let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+            let mut local = secure(stream, &con).unwrap();
+            client_handshake(&mut local, protocol);
+            local.write_u8(1).unwrap();
+            assert_eq!(local.read_u8().unwrap(), 1, "ClientInit passed through the TLS session");
+
+            let credentials = server.join().unwrap().unwrap();
+            assert_eq!(credentials, ("kobo".to_string(), "a password longer than 8 bytes".to_string()), "password not truncated");
+        }
+    }
+
+    #[test]
+    fn x509_fingerprint_pinning() {
+        let (key, cert) = self_signed();
+        let digest = cert.digest(MessageDigest::sha256()).unwrap();
+        let fingerprint: Vec<String> = digest.iter().map(|b| format!("{:02X}", b)).collect();
+        let fingerprint = fingerprint.join(":");
+        let acceptor = || {
+            let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+            acceptor.set_private_key(&key).unwrap();
+            acceptor.set_certificate(&cert).unwrap();
+            acceptor.build()
+        };
+
+        let (port, server) = serve(acceptor(), SubType::X509Plain, PROTOCOL_VERSION);
+        let con = connection(Some(&fingerprint));
+        let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        let mut local = secure(stream, &con).expect("pinned certificate accepted");
+        client_handshake(&mut local, PROTOCOL_VERSION);
+        local.write_u8(0).unwrap();
+        assert_eq!(local.read_u8().unwrap(), 0);
+        server.join().unwrap();
+
+        let (port, server) = serve(acceptor(), SubType::X509Plain, PROTOCOL_VERSION);
+        let wrong = "00".repeat(32);
+        let con = connection(Some(&wrong));
+        let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        assert!(matches!(secure(stream, &con), Err(Error::Auth(_))), "other certificate rejected");
+        assert!(server.join().unwrap().is_none());
+    }
+
+    #[test]
+    fn sub_type_preference() {
+        let mut con = connection(None);
+        assert_eq!(acceptable_sub_types(&con), vec![SubType::TlsPlain, SubType::TlsNone]);
+        con.tls_fingerprint = Some("AB:cd");
+        assert_eq!(acceptable_sub_types(&con), vec![SubType::X509Plain, SubType::X509None]);
+        con.password = None;
+        assert_eq!(acceptable_sub_types(&con), vec![SubType::X509None], "never downgrade to anonymous TLS");
+        assert_eq!(normalize_fingerprint("AB:cd:0F"), "abcd0f");
+    }
+}
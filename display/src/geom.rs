@@ -750,6 +750,124 @@ impl Ord for Rectangle {
     }
 }
 
+/// How many of the latest merged rectangles `RegionSet::coalesce` tries a rectangle with,
+/// which keeps it linear in the number of rectangles.
+const COALESCE_WINDOW: usize = 8;
+
+/// A set of pixels, held as disjoint rectangles.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RegionSet {
+    rects: Vec<Rectangle>,
+}
+
+impl RegionSet {
+    pub fn new() -> RegionSet {
+        RegionSet::default()
+    }
+
+    pub fn rectangles(&self) -> &[Rectangle] {
+        &self.rects
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    /// The number of pixels in the set.
+    pub fn area(&self) -> u32 {
+        self.rects.iter().map(Rectangle::area).sum()
+    }
+
+    pub fn bounding_box(&self) -> Option<Rectangle> {
+        let mut rects = self.rects.iter();
+        let mut bbox = *rects.next()?;
+        for rect in rects {
+            bbox.absorb(rect);
+        }
+        Some(bbox)
+    }
+
+    /// Adds the pixels of `rect` that aren't in the set yet.
+    pub fn union(&mut self, rect: &Rectangle) {
+        if rect.is_empty() {
+            return;
+        }
+        let mut pieces = vec![*rect];
+        for rect in &self.rects {
+            pieces = pieces.iter().flat_map(|piece| subtract(piece, rect)).collect();
+        }
+        self.rects.extend(pieces);
+    }
+
+    /// Removes the pixels of `rect`.
+    pub fn difference(&mut self, rect: &Rectangle) {
+        self.rects = self.rects.iter().flat_map(|piece| subtract(piece, rect)).collect();
+    }
+
+    pub fn clear(&mut self) {
+        self.rects.clear();
+    }
+
+    /// Covers the set with few disjoint rectangles within `bounds`, sorted from top to bottom.
+    /// The rectangles are aligned to multiples of `granularity`, and going from top to bottom, each is
+    /// merged into the bounding box with one of the latest, again and again as it grows, as long as
+    /// the pixels that adds are fewer than `cost`, the price of an update in pixels.
+    pub fn coalesce(&self, bounds: &Rectangle, granularity: i32, cost: u32) -> Vec<Rectangle> {
+        let floor = |value: i32| value.div_euclid(granularity) * granularity;
+        let ceil = |value: i32| (value + granularity - 1).div_euclid(granularity) * granularity;
+        let mut rects: Vec<Rectangle> = self.rects.iter()
+            .filter_map(|rect| rect![floor(rect.min.x), floor(rect.min.y), ceil(rect.max.x), ceil(rect.max.y)].intersection(bounds))
+            .collect();
+        rects.sort_by_key(|rect| (rect.min.y, rect.min.x));
+
+        let mut merged: Vec<Rectangle> = Vec::new();
+        for rect in rects {
+            let mut rect = rect;
+            loop {
+                let start = merged.len().saturating_sub(COALESCE_WINDOW);
+                let cheapest = merged[start..].iter()
+                    .enumerate()
+                    .map(|(i, other)| (merge_overhead(other, &rect), start + i))
+                    .filter(|&(overhead, _)| overhead <= cost)
+                    .min();
+                let Some((_, i)) = cheapest else { break };
+                rect.absorb(&merged.remove(i));
+            }
+            merged.push(rect);
+        }
+
+        // aligned and merged rectangles overlap, so each only keeps the pixels no earlier one covers
+        let mut region = RegionSet::new();
+        for rect in &merged {
+            region.union(rect);
+        }
+        region.rects.sort_by_key(|rect| (rect.min.y, rect.min.x));
+        region.rects
+    }
+}
+
+/// The parts of `a` outside of `b`: up to four bands around the hole `b` makes in `a`.
+fn subtract(a: &Rectangle, b: &Rectangle) -> Vec<Rectangle> {
+    let Some(hole) = a.intersection(b) else {
+        return vec![*a];
+    };
+    let bands = [
+        rect![a.min.x, a.min.y, a.max.x, hole.min.y],
+        rect![a.min.x, hole.max.y, a.max.x, a.max.y],
+        rect![a.min.x, hole.min.y, hole.min.x, hole.max.y],
+        rect![hole.max.x, hole.min.y, a.max.x, hole.max.y],
+    ];
+    bands.into_iter().filter(|band| !band.is_empty()).collect()
+}
+
+/// The pixels the bounding box of `a` and `b` covers outside of both.
+fn merge_overhead(a: &Rectangle, b: &Rectangle) -> u32 {
+    let mut bbox = *a;
+    bbox.absorb(b);
+    let overlap = a.intersection(b).map_or(0, |overlap| overlap.area());
+    bbox.area() - (a.area() + b.area() - overlap)
+}
+
 impl Add for Point {
     type Output = Point;
     fn add(self, rhs: Point) -> Point {
@@ -1327,7 +1445,7 @@ impl Region {
 
 #[cfg(test)]
 mod tests {
-    use super::{divide, LinearDir};
+    use super::{divide, LinearDir, RegionSet};
 
     #[test]
     fn test_linear_dir_opposite() {
@@ -1402,4 +1520,59 @@ mod tests {
         assert_eq!(pt4.rdist2(&rect), 5);
         assert_eq!(pt5.rdist2(&rect), 1);
     }
+
+    #[test]
+    fn region_set_union_and_difference() {
+        let mut region = RegionSet::new();
+        region.union(&rect![0, 0, 10, 10]);
+        region.union(&rect![5, 5, 15, 15]);
+        assert_eq!(region.area(), 175);
+        region.union(&rect![2, 2, 4, 4]);
+        assert_eq!(region.area(), 175);
+        region.difference(&rect![5, 5, 10, 10]);
+        assert_eq!(region.area(), 150);
+        assert_eq!(region.bounding_box(), Some(rect![0, 0, 15, 15]));
+        region.difference(&rect![0, 0, 15, 15]);
+        assert!(region.is_empty());
+    }
+
+    #[test]
+    fn region_set_coalescing() {
+        let bounds = rect![0, 0, 100, 100];
+        let mut region = RegionSet::new();
+        region.union(&rect![1, 1, 7, 7]);
+        region.union(&rect![20, 2, 22, 6]);
+        region.union(&rect![90, 90, 120, 120]);
+        // the neighbours are merged, the far one isn't worth it and gets clipped
+        assert_eq!(region.coalesce(&bounds, 8, 64), vec![rect![0, 0, 24, 8], rect![88, 88, 100, 100]]);
+        assert_eq!(region.coalesce(&bounds, 8, 63), vec![rect![0, 0, 8, 8], rect![16, 0, 24, 8], rect![88, 88, 100, 100]]);
+        assert_eq!(region.coalesce(&bounds, 1, 10_000), vec![rect![1, 1, 100, 100]]);
+    }
+
+    #[test]
+    fn region_set_coalescing_stays_disjoint() {
+        let bounds = rect![0, 0, 400, 300];
+        let mut region = RegionSet::new();
+        let mut seed = 7u32;
+        let mut next = |max: u32| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            ((seed >> 16) % max) as i32
+        };
+        for _ in 0..200 {
+            let (x, y) = (next(400), next(300));
+            region.union(&rect![x, y, x + 1 + next(40), y + 1 + next(40)]);
+        }
+
+        for cost in [0, 64, 64 * 64, 1 << 20] {
+            let rects = region.coalesce(&bounds, 8, cost);
+            for (i, a) in rects.iter().enumerate() {
+                assert!(rects[i + 1..].iter().all(|b| !a.overlaps(b)), "{:?} overlaps with cost {}", a, cost);
+            }
+            let mut covered = region.clone();
+            for rect in &rects {
+                covered.difference(rect);
+            }
+            assert!(covered.rectangles().iter().all(|rect| !rect.overlaps(&bounds)), "everything within the bounds is covered");
+        }
+    }
 }
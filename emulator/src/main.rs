@@ -21,19 +21,20 @@ fn main() -> Result<(), Error> {
     env_logger::init();
 
     let config = local_config();
-    let mut vnc = einkvnc::vnc::connect(config.connection);
 
     let (height, width) = CURRENT_DEVICE.dims;
     info!("size w={} h={}", width, height);
     let mut vnc_fb: Box<dyn Framebuffer> = localbuffer::new(APP_NAME, width, cmp::min(960, height));
     println!("{} is running on a Kobo {}.", APP_NAME, CURRENT_DEVICE.model);
+    let mut vnc = einkvnc::connect(&config, &mut vnc_fb)?;
 
     ctrlc::set_handler(move || {
         println!("received Ctrl+C!");
         std::process::exit(1);
     }).expect("Error setting Ctrl-C handler");
 
-    einkvnc::run(&mut vnc, &mut vnc_fb, &config)
+    einkvnc::run(&mut vnc, &mut vnc_fb, &config)?;
+    Ok(())
 }
 
 fn local_config() -> Config<'static> {
@@ -43,16 +44,35 @@ fn local_config() -> Config<'static> {
             port: 5901, 
             username: None, 
             password: Some("123456"), 
-            exclusive: false 
+            exclusive: false,
+            tls: false,
+            tls_ca: None,
+            tls_fingerprint: None,
+            format: einkvnc::vnc::ColorFormat::Native,
+            local_cursor: false,
+            continuous_updates: false,
+            resize: false,
         },
+        listen: None,
         processing: einkvnc::processing::PostProcConfig { 
             contrast_exp: 1.0, 
             contrast_gray_point: 224.0, 
             white_cutoff: 255 
         },
+        refresh_policy: Box::new(einkvnc::RefreshPreset::BALANCED),
         rotate: 1,
+        auto_rotate: einkvnc::hardware::AutoRotate::Off,
         view_only: true,
         touch_input: "/dev/oblivion".to_string(),
+        keyboards: Vec::new(),
+        keyboard_layout: einkvnc::keyboard::KeyboardLayout::Us,
+        gestures: einkvnc::touch::GestureConfig::default(),
+        stylus: einkvnc::touch::StylusConfig::default(),
+        buttons_input: None,
+        page_turn: einkvnc::hardware::PageTurnConfig::default(),
+        gyroscope_input: None,
+        sleep_disconnect: false,
+        reconnect: true,
     }
 }
 